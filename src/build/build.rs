@@ -1,7 +1,10 @@
+use std::collections::HashMap;
 use crate::config::BuildConfig;
 use rsfs::GenFS;
-use crate::build::{static_files, target_discovery};
+use crate::build::{csp, static_files, target_discovery};
 use crate::build::target_renderer::build_target;
+use crate::build::custom_functions::SassCache;
+use crate::util::md_refs::normalize_path;
 
 fn prepare_output<FS: GenFS>(path: &String, fs: &mut FS) -> anyhow::Result<()> {
     match fs.remove_dir_all(&path) {
@@ -26,9 +29,17 @@ pub fn build<FS: GenFS>(config: &BuildConfig, fs: &mut FS) -> anyhow::Result<()>
     let targets = target_discovery::locate_targets(config)?;
     target_discovery::validate_targets(&targets)?;
 
+    let mut target_paths = HashMap::new();
     for (_, target) in targets.iter() {
-        build_target(config, &static_hashes, target, fs)?
+        target_paths.insert(normalize_path(&target.dir()?), target.config.path.clone());
     }
 
+    let mut sass_cache = SassCache::default();
+    let mut csp_policies = vec![];
+    for (_, target) in targets.iter() {
+        build_target(config, &static_hashes, &target_paths, &mut sass_cache, &mut csp_policies, target, fs)?
+    }
+    csp::write_headers(config, &csp_policies, fs)?;
+
     Ok(())
 }
\ No newline at end of file
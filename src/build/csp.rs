@@ -0,0 +1,100 @@
+use std::io::Write;
+use std::path::PathBuf;
+use base64::prelude::*;
+use itertools::Itertools;
+use rsfs::GenFS;
+use sha2::{Digest, Sha256};
+use crate::config::{BuildConfig, CspConfig, CspMode};
+use crate::util::html::{create_element, insert_node, parse_html_document, select_nodes, serialize_html_document, HTML};
+
+pub const HEADERS_FILE: &str = "_headers";
+
+fn source_hashes(document: &HTML, selector: &str) -> anyhow::Result<String> {
+    Ok(select_nodes(document, selector)?.into_iter()
+        .map(|node| format!("'sha256-{}'", BASE64_STANDARD.encode(Sha256::digest(node.text_contents().as_bytes()))))
+        .unique()
+        .map(|hash| format!(" {}", hash))
+        .join(""))
+}
+
+/// Adds `sources` to the directive `name` of `directives`, or appends `name 'self' sources` if the policy has none.
+fn merge_sources(directives: &mut Vec<String>, name: &str, sources: String) {
+    let existing = directives.iter_mut().find(|directive| {
+        directive.split_whitespace().next().is_some_and(|directive| directive.eq_ignore_ascii_case(name))
+    });
+    match existing {
+        Some(directive) => directive.push_str(&sources),
+        None => directives.push(format!("{} 'self'{}", name, sources)),
+    }
+}
+
+fn page_policy(config: &CspConfig, document: &HTML) -> anyhow::Result<String> {
+    let mut directives = config.policy.split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    merge_sources(&mut directives, "script-src", source_hashes(document, "script:not([src])")?);
+    merge_sources(&mut directives, "style-src", source_hashes(document, "style")?);
+    Ok(directives.join("; "))
+}
+
+/// Computes the policy of a rendered page. In `meta` mode it is inserted into the page head,
+/// in `headers` mode it is returned for `write_headers`.
+pub fn apply_csp(config: &CspConfig, html: String) -> anyhow::Result<(String, Option<String>)> {
+    if config.mode == CspMode::Off {
+        return Ok((html, None));
+    }
+    let document = parse_html_document(html.clone());
+    let policy = page_policy(config, &document)?;
+    match config.mode {
+        CspMode::Meta => {
+            let Some(mut head) = select_nodes(&document, "head")?.into_iter().next() else {
+                anyhow::bail!("Page has no <head> for the Content-Security-Policy meta tag");
+            };
+            insert_node(&mut head, 0, create_element("meta".to_string(), vec![
+                ("http-equiv".to_string(), Some("Content-Security-Policy".to_string())),
+                ("content".to_string(), Some(policy)),
+            ]));
+            Ok((serialize_html_document(document)?, None))
+        }
+        _ => Ok((html, Some(policy))),
+    }
+}
+
+/// Writes collected `(page url, policy)` pairs in the `_headers` format understood by static hosts.
+pub fn write_headers<FS: GenFS>(config: &BuildConfig, policies: &[(String, String)], fs: &mut FS) -> anyhow::Result<()> {
+    if config.csp.mode != CspMode::Headers {
+        return Ok(());
+    }
+    let mut file = fs.create_file(PathBuf::from(&config.output).join(HEADERS_FILE))?;
+    for (url, policy) in policies {
+        writeln!(file, "{}\n  Content-Security-Policy: {}", url, policy)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_policy() {
+        let config = CspConfig { mode: CspMode::Meta, policy: "default-src 'self';".to_string() };
+        let html = "<html><head><style>p{}</style></head><body><script src=\"/a.js\"></script><script>go()</script></body></html>".to_string();
+        let (html, header) = apply_csp(&config, html).unwrap();
+        assert!(header.is_none());
+        assert!(html.starts_with("<html><head><meta http-equiv=\"Content-Security-Policy\" content=\"default-src 'self'; script-src 'self' 'sha256-"));
+        assert_eq!(html.matches("'sha256-").count(), 2);
+    }
+
+    #[test]
+    fn test_hashes_merge_into_policy_directives() {
+        let config = CspConfig { mode: CspMode::Headers, policy: "default-src 'self'; Script-Src 'self' https://cdn.example;".to_string() };
+        let (_, policy) = apply_csp(&config, "<html><head></head><body><script>go()</script></body></html>".to_string()).unwrap();
+        let policy = policy.unwrap();
+        assert!(policy.starts_with("default-src 'self'; Script-Src 'self' https://cdn.example 'sha256-"), "{}", policy);
+        assert!(policy.ends_with("; style-src 'self'"), "{}", policy);
+        assert_eq!(policy.to_lowercase().matches("script-src").count(), 1);
+    }
+}
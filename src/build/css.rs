@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::path::Path;
+use anyhow::anyhow;
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+use lightningcss::targets::{Browsers, Targets};
+use parcel_sourcemap::SourceMap;
+use rsfs::GenFS;
+use crate::config::CssConfig;
+
+pub struct ProcessedCss {
+    pub code: String,
+    pub map: Option<String>,
+}
+
+fn targets(config: &CssConfig) -> anyhow::Result<Targets> {
+    if config.targets.is_empty() {
+        return Ok(Targets::default());
+    }
+    Browsers::from_browserslist([config.targets.as_str()])
+        .map(Targets::from)
+        .map_err(|e| anyhow!("Invalid `build.css.targets` query `{}`: {}", config.targets, e))
+}
+
+/// Prefixes, lowers and optionally minifies `css` according to `config`.
+pub fn process_css(config: &CssConfig, filename: &str, css: &str) -> anyhow::Result<ProcessedCss> {
+    let targets = targets(config)?;
+    let mut sheet = StyleSheet::parse(css, ParserOptions { filename: filename.to_string(), ..Default::default() })
+        .map_err(|e| anyhow!("Can not parse css `{}`: {}", filename, e))?;
+    sheet.minify(MinifyOptions { targets, ..Default::default() })
+        .map_err(|e| anyhow!("Can not process css `{}`: {}", filename, e))?;
+
+    let mut source_map = config.source_maps.then(|| {
+        let mut map = SourceMap::new("/");
+        map.add_source(filename);
+        map
+    });
+    let res = sheet.to_css(PrinterOptions { minify: config.minify, source_map: source_map.as_mut(), targets, ..Default::default() })
+        .map_err(|e| anyhow!("Can not print css `{}`: {}", filename, e))?;
+    let map = match source_map {
+        Some(mut map) => {
+            map.set_source_content(0, css)?;
+            Some(map.to_json(None)?)
+        }
+        None => None,
+    };
+    Ok(ProcessedCss { code: res.code, map })
+}
+
+/// Runs `process_css` if `[build.css]` enables any processing, otherwise passes `css` through.
+pub fn finish_css(config: &CssConfig, source: &Path, css: String) -> anyhow::Result<ProcessedCss> {
+    if config.enabled() {
+        process_css(config, &source.to_string_lossy(), &css)
+    } else {
+        Ok(ProcessedCss { code: css, map: None })
+    }
+}
+
+/// Writes processed css to `out_file` and its source map, if any, next to it.
+/// Returns the written css, which ends with the `sourceMappingURL` comment when there is a map.
+pub fn write_processed<FS: GenFS>(out_file: &Path, css: ProcessedCss, fs: &mut FS) -> anyhow::Result<String> {
+    if let Some(parent) = out_file.parent() {
+        fs.create_dir_all(parent)?;
+    }
+    let ProcessedCss { mut code, map } = css;
+    if let Some(map) = map {
+        let mut map_file = out_file.as_os_str().to_owned();
+        map_file.push(".map");
+        fs.create_file(Path::new(&map_file))?.write_all(map.as_bytes())?;
+        let name = out_file.file_name().unwrap_or_default().to_string_lossy();
+        code.push_str(&format!("\n/*# sourceMappingURL={}.map */\n", name));
+    }
+    fs.create_file(out_file)?.write_all(code.as_bytes())?;
+    Ok(code)
+}
+
+pub fn write_css<FS: GenFS>(config: &CssConfig, source: &Path, out_file: &Path, css: String, fs: &mut FS) -> anyhow::Result<()> {
+    write_processed(out_file, finish_css(config, source, css)?, fs)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_and_minify() {
+        let config = CssConfig { targets: "safari 10".to_string(), minify: true, source_maps: false };
+        let res = process_css(&config, "a.css", "a {\n  user-select: none;\n}\n").unwrap();
+        assert_eq!(res.code, "a{-webkit-user-select:none;user-select:none}");
+        assert!(res.map.is_none());
+    }
+}
@@ -1,12 +1,346 @@
 use std::path::{Path, PathBuf};
-use minijinja::{Error, State, Value};
+use std::sync::{Arc, OnceLock};
+use minijinja::value::{from_args, Enumerator, Kwargs, Object};
+use minijinja::{context, Error, ErrorKind, State, Value};
 use serde::de::Error as _;
-use crate::build::renderer_state::{get_state, lock_state, RendererState, RENDERER_STATE};
+use serde::Deserialize;
+use toml::{Spanned, Table};
+use crate::build::custom_functions::slugify;
+use crate::build::custom_functions::data::{read_data_file, DATA_BLOCK_EXTENSIONS};
+use crate::build::default_theme::DEFAULT_GROUP_TEMPLATE;
+use crate::build::renderer_state::{get_state, lock_state, RendererState};
+use crate::config::MarkdownConfig;
+use crate::util::diagnostic::Diagnostic;
 use crate::util::error_mappers::map_io_error;
+use crate::util::ordering::{natural_cmp, split_order_prefix};
 
-use crate::util::md_parser::Context;
+use crate::util::md_parser::{merge_tables, Context, ContextParams};
 
-pub fn blocks(state: &State, mut dir: String, default_template: Option<String>) -> Result<Value, Error> {
+/// Group data file of a subdirectory, it is not a block itself.
+const GROUP_INDEX: &str = "_index.md";
+/// Default frontmatter for the markdown blocks of a directory and all directories below it.
+const DEFAULTS_FILE: &str = "_defaults.toml";
+
+/// One file of a blocks directory, `html()` renders it on first use.
+#[derive(Debug)]
+pub struct Block {
+    /// Position in the returned list, after sorting and filtering.
+    pub index: usize,
+    pub name: String,
+    /// File stem without its `1. ` ordering prefix.
+    pub title: String,
+    pub order: Option<u64>,
+    pub slug: String,
+    pub path: PathBuf,
+    pub template: String,
+    /// Parsed markdown or data file, `None` for `.html` blocks which are rendered as templates themselves
+    /// and for groups without `_index.md`.
+    pub context: Option<Context>,
+    /// Blocks of a subdirectory read with `recursive=true`, its template gets them as `children`.
+    pub children: Option<Vec<Arc<Block>>>,
+    html: OnceLock<String>,
+}
+
+impl Block {
+    fn field(&self, key: &str) -> Option<Value> {
+        Some(match key {
+            "index" => Value::from(self.index),
+            "name" => Value::from(&self.name),
+            "title" => Value::from(&self.title),
+            "order" => Value::from(self.order),
+            "slug" => Value::from(&self.slug),
+            "path" => Value::from(self.path.to_string_lossy()),
+            "template" => Value::from(&self.template),
+            "config" => self.context.as_ref().map_or(Value::from(()), |c| Value::from_serialize(&c.config)),
+            "data" => self.context.as_ref().map_or(Value::from(()), |c| c.data.clone()),
+            "group" => Value::from(self.children.is_some()),
+            "children" => self.children_value(),
+            _ => return None,
+        })
+    }
+
+    /// Resolves a dotted attribute path such as `config.meta.weight`, missing attributes at any level are undefined.
+    fn lookup(&self, path: &str) -> Result<Value, Error> {
+        let mut parts = path.split('.');
+        let mut value = self.field(parts.next().unwrap_or_default()).unwrap_or(Value::UNDEFINED);
+        for part in parts {
+            if value.is_undefined() || value.is_none() {
+                return Ok(Value::UNDEFINED);
+            }
+            value = value.get_attr(part)?;
+        }
+        Ok(value)
+    }
+
+    fn children_value(&self) -> Value {
+        match &self.children {
+            Some(children) => Value::from_iter(children.iter().cloned().map(Value::from_dyn_object)),
+            None => Value::from(()),
+        }
+    }
+
+    fn render_html(&self, state: &State) -> Result<String, Error> {
+        if let Some(html) = self.html.get() {
+            return Ok(html.clone());
+        }
+        let template = state.env().get_template(&self.template)?;
+        let html = match (&self.context, &self.children) {
+            (Some(context), Some(_)) => template.render(context! { children => self.children_value(), ..Value::from_serialize(context) })?,
+            // Groups without `_index.md` still get empty `config` and `data`, so the template needs no checks for them.
+            (None, Some(_)) => template.render(context! { children => self.children_value(), config => context! {}, data => context! {} })?,
+            (Some(context), None) => template.render(context)?,
+            (None, None) => template.render(())?,
+        };
+        Ok(self.html.get_or_init(|| html).clone())
+    }
+}
+
+impl Object for Block {
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        self.field(key.as_str()?)
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        Enumerator::Str(&["index", "name", "title", "order", "slug", "path", "template", "config", "data", "group", "children"])
+    }
+
+    fn call_method(self: &Arc<Self>, state: &State, method: &str, args: &[Value]) -> Result<Value, Error> {
+        match method {
+            "html" => {
+                let () = from_args(args)?;
+                Ok(Value::from_safe_string(self.render_html(state)?))
+            }
+            _ => Err(Error::from(ErrorKind::UnknownMethod)),
+        }
+    }
+}
+
+/// Keyword arguments of `blocks` and `collect_blocks`.
+struct BlockQuery {
+    /// Subdirectories become group blocks instead of being skipped.
+    recursive: bool,
+    /// Template of groups whose `_index.md` does not set one.
+    group_template: String,
+    sort_by: Option<String>,
+    reverse: bool,
+    /// An attribute path that has to be truthy, or a map of attribute paths to the values they have to equal.
+    filter: Option<Value>,
+}
+
+impl BlockQuery {
+    fn from_kwargs(kwargs: &Kwargs) -> Result<Self, Error> {
+        let query = Self {
+            recursive: kwargs.get::<Option<bool>>("recursive")?.unwrap_or(false),
+            group_template: kwargs.get::<Option<String>>("group_template")?.unwrap_or_else(|| DEFAULT_GROUP_TEMPLATE.to_string()),
+            sort_by: kwargs.get("sort_by")?,
+            reverse: kwargs.get::<Option<bool>>("reverse")?.unwrap_or(false),
+            filter: kwargs.get("where")?,
+        };
+        kwargs.assert_all_used()?;
+        Ok(query)
+    }
+
+    /// Groups are always kept, `where` applies to the blocks inside them.
+    fn matches(&self, block: &Block) -> Result<bool, Error> {
+        let Some(filter) = &self.filter else {
+            return Ok(true);
+        };
+        if block.children.is_some() {
+            return Ok(true);
+        }
+        if let Some(path) = filter.as_str() {
+            return Ok(block.lookup(path)?.is_true());
+        }
+        for path in filter.try_iter()? {
+            let expected = filter.get_item(&path)?;
+            let path = path.as_str().ok_or_else(|| Error::custom(format!("`where` keys have to be strings, got `{}`", path)))?;
+            if block.lookup(path)? != expected {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Files are already in natural order, the stable sort keeps it for equal keys, also when reversed.
+    fn apply(&self, blocks: Vec<Block>) -> Result<Vec<Arc<Block>>, Error> {
+        let mut kept = vec![];
+        for block in blocks {
+            if self.matches(&block)? {
+                kept.push(block);
+            }
+        }
+        let mut blocks = kept;
+        if let Some(sort_by) = &self.sort_by {
+            let mut keyed = blocks.into_iter()
+                .map(|block| Ok((block.lookup(sort_by)?, block)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            match self.reverse {
+                true => keyed.sort_by(|(a, _), (b, _)| b.cmp(a)),
+                false => keyed.sort_by(|(a, _), (b, _)| a.cmp(b)),
+            }
+            blocks = keyed.into_iter().map(|(_, block)| block).collect();
+        } else if self.reverse {
+            blocks.reverse();
+        }
+        Ok(blocks.into_iter().enumerate().map(|(index, block)| Arc::new(Block { index, ..block })).collect())
+    }
+}
+
+/// Merges the `_defaults.toml` of `dir`, if there is one, on top of the defaults of its parent directories.
+fn dir_defaults(dir: &Path, mut defaults: Table) -> Result<Table, Error> {
+    let path = dir.join(DEFAULTS_FILE);
+    if !path.is_file() {
+        return Ok(defaults);
+    }
+    let text = std::fs::read_to_string(&path).map_err(map_io_error)?;
+    let table = toml::from_str::<Table>(&text).map_err(|e| {
+        let diagnostic = Diagnostic::new(&path, format!("Invalid TOML in defaults: {}", e.message()));
+        match e.span() {
+            Some(span) => diagnostic.with_span(&text, span),
+            None => diagnostic,
+        }
+    })?;
+    check_defaults(&path, &text)?;
+    merge_tables(&mut defaults, table);
+    Ok(defaults)
+}
+
+/// Keys of `_defaults.toml` that the block parser reads itself rather than passing on as `config`.
+#[derive(Deserialize)]
+struct ReservedDefaults {
+    template: Option<Spanned<toml::Value>>,
+    templating: Option<Spanned<toml::Value>>,
+}
+
+/// Rejects the `template` and `templating` values that frontmatter rejects too.
+fn check_defaults(path: &Path, text: &str) -> Result<(), Error> {
+    let reserved: ReservedDefaults = toml::from_str(text).map_err(|e| Error::custom(e.to_string()))?;
+    if let Some(template) = reserved.template.filter(|t| !t.get_ref().is_str()) {
+        return Err(Diagnostic::new(path, format!("Invalid `template` value in defaults: {}", template.get_ref()))
+            .with_span(text, template.span())
+            .with_hint("use a template name like `template = \"card.html\"`")
+            .into());
+    }
+    if let Some(templating) = reserved.templating.filter(|t| !t.get_ref().is_bool()) {
+        return Err(Diagnostic::new(path, format!("Invalid `templating` value in defaults: {}", templating.get_ref()))
+            .with_span(text, templating.span())
+            .with_hint("use `templating = true` or `templating = false`")
+            .into());
+    }
+    Ok(())
+}
+
+/// Everything shared by the blocks of one `blocks()` call, including those of nested directories.
+struct BlockReader<'a> {
+    state: &'a State<'a, 'a>,
+    renderer_state: Arc<RendererState>,
+    target_root: PathBuf,
+    /// Target config file, a `.toml` file that is not a data block.
+    index_toml_name: String,
+    markdown: MarkdownConfig,
+    globals: Value,
+    default_template: Option<String>,
+    query: BlockQuery,
+}
+
+impl BlockReader<'_> {
+    fn parse(&self, path: &Path, default_template: &Option<String>, defaults: &Table) -> Result<Context, Error> {
+        let text = std::fs::read_to_string(path).map_err(map_io_error)?;
+        Context::new(&text, ContextParams {
+            path,
+            default_template,
+            env: self.state.env(),
+            refs: Some(self.renderer_state.as_ref()),
+            markdown: &self.markdown,
+            globals: self.globals.clone(),
+            defaults,
+        })
+    }
+
+    /// Data files have no frontmatter, their template comes from `_defaults.toml` or the `blocks()` call
+    /// and the rest of the defaults is their `config`.
+    fn data_context(&self, path: &Path, defaults: &Table) -> Result<Context, Error> {
+        let mut config = defaults.clone();
+        config.remove("markdown");
+        config.remove("templating");
+        let template = match config.remove("template") {
+            Some(toml::Value::String(template)) => Some(template),
+            _ => self.default_template.clone(),
+        };
+        let template = template.ok_or_else(|| Diagnostic::new(path, "No template specified.")
+            .with_hint("set `template` in `_defaults.toml` or pass a default template to `blocks()`"))?;
+        Ok(Context { template, config, data: read_data_file(path)?, ser_data: None })
+    }
+
+    /// `defaults` already include the `_defaults.toml` of `blocks_dir` itself.
+    fn read_dir(&self, blocks_dir: &Path, defaults: &Table) -> Result<Vec<Arc<Block>>, Error> {
+        let mut entries = vec![];
+        for entry in blocks_dir.read_dir().map_err(map_io_error)? {
+            let entry = entry.map_err(map_io_error)?.path();
+            if entry.is_dir() {
+                if self.query.recursive {
+                    entries.push(entry);
+                }
+                continue;
+            }
+            if !entry.is_file() || entry.file_name().is_some_and(|name| name == GROUP_INDEX || name == DEFAULTS_FILE || *name == *self.index_toml_name) {
+                continue;
+            }
+            match entry.extension().and_then(|ext| ext.to_str()) {
+                Some(ext) if ext == "md" || ext == "html" || DATA_BLOCK_EXTENSIONS.contains(&ext) => {
+                    entries.push(entry);
+                }
+                _ => {}
+            }
+        }
+        entries.sort_by(|a, b| natural_cmp(&a.file_name().unwrap_or_default().to_string_lossy(), &b.file_name().unwrap_or_default().to_string_lossy()));
+        let mut blocks = vec![];
+        for entry in entries {
+            blocks.push(self.read_block(entry, defaults)?);
+        }
+        self.query.apply(blocks)
+    }
+
+    fn read_block(&self, entry: PathBuf, defaults: &Table) -> Result<Block, Error> {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let stem = match entry.is_dir() {
+            true => name.clone(),
+            false => entry.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        let (order, title) = split_order_prefix(&stem);
+        let title = title.to_string();
+        let slug = slugify(title.clone());
+        let (template, context, children) = if entry.is_dir() {
+            let defaults = dir_defaults(&entry, defaults.clone())?;
+            let index = entry.join(GROUP_INDEX);
+            let context = match index.is_file() {
+                true => {
+                    // `template` of the defaults is meant for the blocks of the group, not for its `_index.md`.
+                    let mut index_defaults = defaults.clone();
+                    index_defaults.remove("template");
+                    Some(self.parse(&index, &Some(self.query.group_template.clone()), &index_defaults)?)
+                }
+                false => None,
+            };
+            let template = context.as_ref().map_or_else(|| self.query.group_template.clone(), |c| c.template.clone());
+            (template, context, Some(self.read_dir(&entry, &defaults)?))
+        } else if let Some(ext) = entry.extension() && ext == "html" {
+            let template = entry.strip_prefix(self.target_root.as_path()).map_err(|_| Error::custom(format!("Failed to strip prefix `{}` for `{}` .", self.target_root.display(), entry.display())))?;
+            (template.to_str().ok_or(
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not utf-8 path")
+            ).map_err(|e| {Error::custom(format!("{}", e))})?.to_string(), None, None)
+        } else if entry.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| DATA_BLOCK_EXTENSIONS.contains(&ext)) {
+            let context = self.data_context(&entry, defaults)?;
+            (context.template.clone(), Some(context), None)
+        } else {
+            let context = self.parse(&entry, &self.default_template, defaults)?;
+            (context.template.clone(), Some(context), None)
+        };
+        Ok(Block { index: 0, name, title, order, slug, path: entry, template, context, children, html: OnceLock::new() })
+    }
+}
+
+fn read_blocks(state: &State, mut dir: String, default_template: Option<String>, query: BlockQuery) -> Result<Vec<Arc<Block>>, Error> {
     if dir.starts_with("./") {
         dir = PathBuf::from(state.name()).parent().unwrap_or(Path::new("../../..")).join(dir).to_str().ok_or(
             Error::custom("Not a valid unicode")
@@ -16,8 +350,12 @@ pub fn blocks(state: &State, mut dir: String, default_template: Option<String>)
     let renderer_state = get_state(state)?;
     let locked_state = lock_state(&renderer_state)?;
     let target_root = locked_state.target_path.clone();
+    let markdown = locked_state.config.markdown.clone();
+    let index_toml_name = locked_state.config.index_toml_name.clone();
+    let globals = context! {
+        site => Value::from_serialize(&locked_state.config),
+    };
     drop(locked_state);
-    drop(renderer_state);
 
     let blocks_dir = target_root.join(dir);
     if !blocks_dir.exists() {
@@ -26,36 +364,149 @@ pub fn blocks(state: &State, mut dir: String, default_template: Option<String>)
     if !blocks_dir.is_dir() {
         return Err(Error::custom(format!("Blocks directory `{}` is not a directory.", blocks_dir.display())));
     }
-    let mut files = vec![];
-    for entry in blocks_dir.read_dir().map_err(map_io_error)? {
-        let entry = entry.map_err(map_io_error)?.path();
-
-        if !entry.is_file() {
-            continue;
-        }
-        match entry.extension() {
-            Some(ext) if ext == "md" || ext == "html" => {
-                files.push(entry);
-            }
-            _ => {}
-        }
+    let mut defaults = dir_defaults(&target_root, Table::new())?;
+    let mut dir = target_root.clone();
+    for component in blocks_dir.strip_prefix(&target_root).into_iter().flat_map(Path::components) {
+        dir.push(component);
+        defaults = dir_defaults(&dir, defaults)?;
     }
+    let reader = BlockReader { state, renderer_state, target_root, index_toml_name, markdown, globals, default_template, query };
+    reader.read_dir(&blocks_dir, &defaults)
+}
+
+/// Blocks of `dir` as objects for templates that loop over, slice or wrap them.
+pub fn collect_blocks(state: &State, dir: String, default_template: Option<String>, kwargs: Kwargs) -> Result<Value, Error> {
+    let query = BlockQuery::from_kwargs(&kwargs)?;
+    Ok(Value::from_iter(read_blocks(state, dir, default_template, query)?.into_iter().map(Value::from_dyn_object)))
+}
+
+pub fn blocks(state: &State, dir: String, default_template: Option<String>, kwargs: Kwargs) -> Result<Value, Error> {
+    let query = BlockQuery::from_kwargs(&kwargs)?;
     let mut results = vec![];
-    for entry in itertools::sorted(files.into_iter()) {
-        if let Some(ext) = entry.extension() && ext == "html"  {
-            let entry = entry.strip_prefix(target_root.as_path()).map_err(|_| Error::custom(format!("Failed to strip prefix `{}` for `{}` .", target_root.display(), entry.display())))?;
-            results.push(state.env().get_template(entry.to_str().ok_or(
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not utf-8 path")
-            ).map_err(|e| {Error::custom(format!("{}", e))})?)?.render(())?);
-            continue;
-        }
+    for block in read_blocks(state, dir, default_template, query)? {
+        results.push(block.render_html(state)?);
+    }
+    Ok(Value::from_safe_string(results.join("\n")))
+}
 
-        let text = std::fs::read_to_string(&entry).map_err(map_io_error)?;
-        let context = Context::new(&text, &default_template, state.env())?;
+#[cfg(test)]
+mod tests {
+    use crate::build::target_renderer::test_site::TestSite;
 
-        let template = state.env().get_template(context.template.as_str())?;
-        results.push(template.render(&context)?);
+    #[test]
+    fn test_data_blocks() {
+        let site = TestSite::new("data-blocks", &[
+            ("sources/home/index.toml", "path = \"/\"\ntitle = \"Home\"\n"),
+            ("sources/home/1. intro.md", "# title\nHi\n"),
+            ("sources/b.html", "[{{ data.title }}]"),
+            ("sources/home/plans/_defaults.toml", "template = \"plan.html\"\ncurrency = \"EUR\"\n"),
+            ("sources/home/plans/1. basic.yaml", "name: Basic\nprice: 5\n"),
+            ("sources/home/plans/2. pro.json", r#"{"name": "Pro", "price": 20}"#),
+            ("sources/home/plans/10. team.toml", "name = \"Team\"\nprice = 50\n"),
+            ("sources/plan.html", "{{ data.name }} {{ data.price }} {{ config.currency }};"),
+        ]);
+        assert_eq!(site.render("home", r#"{{ blocks(".", "b.html") }}"#).unwrap(), "[Hi]");
+        assert_eq!(site.render("home", r#"{{ blocks("plans") }}"#).unwrap(), "Basic 5 EUR;\nPro 20 EUR;\nTeam 50 EUR;");
     }
 
-    Ok(Value::from_safe_string(results.join("\n")))
-}
\ No newline at end of file
+    #[test]
+    fn test_default_block_renders_markdown() {
+        let site = TestSite::new("default-block", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/1. a.md", "# title\nA & B\n# content\nSome *text*\n"),
+            ("sources/home/2. b.md", "# content {html}\nMore *text*\n\n    indented code\n\n    more\n"),
+            ("sources/home/3. c.md", "# content\n```\nfenced\n\ncode\n```\n"),
+        ]);
+        let html = site.render("home", r#"{{ blocks(".", "default/block.html") }}"#).unwrap();
+        assert!(html.contains("<h2>A &amp; B</h2>"), "{}", html);
+        assert!(html.contains("<p>Some <em>text</em></p>"), "{}", html);
+        assert!(html.contains("<p>More <em>text</em></p>"), "{}", html);
+        // Html sections are already rendered, a second markdown pass would break the code blocks apart.
+        assert!(html.contains("<pre><code>indented code\n\nmore\n</code></pre>"), "{}", html);
+        assert!(html.contains("<pre><code>fenced\n\ncode\n</code></pre>"), "{}", html);
+        assert_eq!(site.render("home", r#"{% for b in collect_blocks(".", "t.html") %}{% if b.data.content is html %}html{% else %}literal{% endif %} {% endfor %}"#).unwrap(), "literal html literal ");
+    }
+
+    #[test]
+    fn test_invalid_defaults() {
+        let site = TestSite::new("invalid-defaults", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/a/_defaults.toml", "class = \"card\"\ntemplating = \"yes\"\n"),
+            ("sources/home/a/1. a.md", "# t\na\n"),
+            ("sources/home/b/_defaults.toml", "template = 5\n"),
+            ("sources/home/b/1. b.md", "# t\nb\n"),
+        ]);
+        let err = site.render("home", r#"{{ blocks("a", "t.html") }}"#).unwrap_err().to_string();
+        assert!(err.contains("Invalid `templating` value in defaults: \"yes\""), "{}", err);
+        assert!(err.contains("_defaults.toml:2:14"), "{}", err);
+        let err = site.render("home", r#"{{ blocks("b", "t.html") }}"#).unwrap_err().to_string();
+        assert!(err.contains("Invalid `template` value in defaults: 5"), "{}", err);
+        assert!(err.contains("_defaults.toml:1:12"), "{}", err);
+    }
+
+    #[test]
+    fn test_block_objects() {
+        let site = TestSite::new("block-objects", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/blocks/10. About us.md", "+++\nlang = \"en\"\n+++\n# t\nabout\n"),
+            ("sources/home/blocks/2_intro.md", "# t\nintro\n"),
+            ("sources/home/blocks/plain.md", "+++\ntemplate = \"missing.html\"\n+++\n# t\nplain\n"),
+            ("sources/t.html", "<p>{{ data.t }}</p>"),
+        ]);
+        let attributes = site.render("home", r#"{% for b in collect_blocks("blocks", "t.html") %}{{ b.index }}|{{ b.name }}|{{ b.title }}|{{ b.order | tojson }}|{{ b.slug }}|{{ b.template }}|{{ b.config.lang }}|{{ b.group | tojson }};{% endfor %}"#).unwrap();
+        assert_eq!(attributes, "0|2_intro.md|intro|2|intro|t.html||false;1|10. About us.md|About us|10|about-us|t.html|en|false;2|plain.md|plain|null|plain|missing.html||false;");
+        // `html()` renders on first use, so a block whose template is missing only fails when it is rendered.
+        let html = site.render("home", r#"{% set b = collect_blocks("blocks", "t.html")[1] %}{{ b.html() }}{{ b.html() }}"#).unwrap();
+        assert_eq!(html, "<p>about</p><p>about</p>");
+        let err = site.render("home", r#"{% for b in collect_blocks("blocks", "t.html") %}{{ b.html() }}{% endfor %}"#).unwrap_err();
+        assert!(err.to_string().contains("missing.html"), "{}", err);
+    }
+
+    #[test]
+    fn test_recursive_groups() {
+        let site = TestSite::new("block-groups", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/blocks/1. a.md", "# t\na\n"),
+            ("sources/home/blocks/2. cards/_index.md", "+++\ntemplate = \"cards.html\"\n+++\n# t\nCards\n"),
+            ("sources/home/blocks/2. cards/_defaults.toml", "template = \"card.html\"\n"),
+            ("sources/home/blocks/2. cards/1. x.md", "+++\nshow = true\n+++\n# t\nx\n"),
+            ("sources/home/blocks/2. cards/2. y.md", "# t\ny\n"),
+            ("sources/home/blocks/3. plain/1. z.md", "+++\nshow = true\n+++\n# t\nz\n"),
+            ("sources/t.html", "[{{ data.t }}]"),
+            ("sources/cards.html", "<{{ data.t }}:{% for c in children %}{{ c.html() }}{% endfor %}>"),
+            ("sources/card.html", "({{ data.t }})"),
+            ("sources/g.html", "{{ config | length }}{{ data | length }}{% for c in children %}{{ c.html() }}{% endfor %}"),
+        ]);
+        assert_eq!(site.render("home", r#"{{ blocks("blocks", "t.html") }}"#).unwrap(), "[a]");
+        assert_eq!(site.render("home", r#"{{ blocks("blocks", "t.html", recursive=true, group_template="g.html") }}"#).unwrap(), "[a]\n<Cards:(x)(y)>\n00[z]");
+        // `_index.md` is the group's own data and never one of its children.
+        let tree = site.render("home", r#"{% for b in collect_blocks("blocks", "t.html", recursive=true) %}{{ b.title }}{% if b.group %}({% for c in b.children %}{{ c.index }}{{ c.title }}{% endfor %}){% endif %} {% endfor %}"#).unwrap();
+        assert_eq!(tree, "a cards(0x1y) plain(0z) ");
+        let filtered = site.render("home", r#"{% for b in collect_blocks("blocks", "t.html", recursive=true, where="config.show") %}{{ b.title }}{% if b.group %}({% for c in b.children %}{{ c.title }}{% endfor %}){% endif %} {% endfor %}"#).unwrap();
+        assert_eq!(filtered, "cards(x) plain(z) ");
+        // Without `group_template` a group without `_index.md` uses the built-in one.
+        let html = site.render("home", r#"{{ blocks("blocks/3. plain", "t.html") }}|{{ blocks("blocks", "t.html", recursive=true) }}"#).unwrap();
+        assert!(html.starts_with("[z]|"), "{}", html);
+        assert!(html.contains("<section class=\"group\">") && html.contains("[z]"), "{}", html);
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let site = TestSite::new("sort-blocks", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/1. a.md", "+++\nlang = \"en\"\n[meta]\nweight = 2\n+++\n# t\na\n"),
+            ("sources/home/2. b.md", "+++\nlang = \"de\"\nfeatured = true\n+++\n# t\nb\n"),
+            ("sources/home/10. c.md", "+++\nlang = \"en\"\nfeatured = true\n[meta]\nweight = 1\n+++\n# t\nc\n"),
+        ]);
+        let titles = |args: &str| site.render("home", &format!(
+            r#"{{% for b in collect_blocks(".", "t.html", {}) %}}{{{{ b.index }}}}{{{{ b.title }}}} {{% endfor %}}"#, args,
+        )).unwrap();
+        // `b` has no `[meta]`, it sorts first as undefined and keeps its file order among equal keys.
+        assert_eq!(titles(r#"sort_by="config.meta.weight""#), "0b 1c 2a ");
+        assert_eq!(titles(r#"sort_by="config.meta.weight", reverse=true"#), "0a 1c 2b ");
+        assert_eq!(titles("reverse=true"), "0c 1b 2a ");
+        assert_eq!(titles(r#"where="config.featured""#), "0b 1c ");
+        assert_eq!(titles(r#"where={"config.lang": "en", "config.meta.weight": 1}"#), "0c ");
+        assert_eq!(titles(r#"where={"config.missing.key": 1}"#), "");
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use minijinja::value::Kwargs;
+use minijinja::{Error, State, Value};
+use serde::de::Error as _;
+use crate::build::renderer_state::{get_state, lock_state, _RendererState};
+use crate::build::static_files::sha1_hex;
+use crate::build::themes::template_roots;
+use crate::util::error_mappers::map_io_error;
+
+pub const COMPONENTS_DIR: &str = "components";
+
+/// Component directory relative to the template roots, looked up in the same order as templates:
+/// the target dir, the site source and then the themes.
+fn find_component(state: &_RendererState, name: &str) -> Option<(PathBuf, PathBuf)> {
+    let dir = Path::new(COMPONENTS_DIR).join(name);
+    let mut roots = template_roots(&state.config);
+    roots.insert(0, state.target_path.clone());
+    roots.into_iter()
+        .map(|root| root.join(&dir))
+        .find(|path| path.join(format!("{}.html", name)).is_file())
+        .map(|path| (dir, path))
+}
+
+/// Registers the component's own stylesheet with the page sass bundle and its script with the page scripts.
+fn register_assets(state: &mut _RendererState, name: &str, dir: &Path, source_dir: &Path) -> Result<(), Error> {
+    for ext in ["scss", "sass"] {
+        let style = source_dir.join(format!("{}.{}", name, ext));
+        if style.is_file() {
+            state.requested_sass.add(style);
+        }
+    }
+
+    let script = source_dir.join(format!("{}.js", name));
+    if script.is_file() {
+        let bytes = std::fs::read(&script).map_err(map_io_error)?;
+        // Targets and themes can have their own component with the same name, so the file is named by its content.
+        let out_file = PathBuf::from(&state.config.static_output).join(dir).join(format!("{}.{}.js", name, sha1_hex(&bytes)));
+        let url = PathBuf::from(&state.config.prefix).join(&out_file);
+        state.page_assets.add_script(url.display().to_string());
+        state.requested_assets.insert(out_file, script);
+    }
+    Ok(())
+}
+
+/// Renders `components/{name}/{name}.html` with `props` as context.
+/// `{name}.scss` and `{name}.js` next to it are added to the page bundle and `scripts_slot()`.
+pub fn component(state: &State, name: String, props: Kwargs) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let mut locked_state = lock_state(&renderer_state)?;
+    let (dir, source_dir) = find_component(&locked_state, &name).ok_or_else(|| {
+        Error::custom(format!("Component `{}` not found, expected `{}/{}/{}.html`", name, COMPONENTS_DIR, name, name))
+    })?;
+    register_assets(&mut locked_state, &name, &dir, &source_dir)?;
+    drop(locked_state);
+
+    let mut context = BTreeMap::new();
+    for key in props.args() {
+        context.insert(key.to_string(), props.get::<Value>(key)?);
+    }
+    props.assert_all_used()?;
+
+    let template = dir.join(format!("{}.html", name));
+    let template = template.to_str().ok_or_else(|| Error::custom("Not a valid unicode"))?;
+    Ok(Value::from_safe_string(state.env().get_template(template)?.render(context)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use rsfs::GenFS;
+    use crate::build::target_renderer::test_site::TestSite;
+
+    #[test]
+    fn test_component_scripts_per_target() {
+        let site = TestSite::new("component-scripts", &[
+            ("sources/a/index.toml", "path = \"/a/\"\ntemplate = \"page.html\"\n"),
+            ("sources/a/components/card/card.html", "a"),
+            ("sources/a/components/card/card.js", "console.log('a');"),
+            ("sources/b/index.toml", "path = \"/b/\"\ntemplate = \"page.html\"\n"),
+            ("sources/b/components/card/card.html", "b"),
+            ("sources/b/components/card/card.js", "console.log('b');"),
+            ("sources/page.html", "{{ component(\"card\") }}{{ scripts_slot() }}"),
+        ]);
+        let mut fs = rsfs::mem::FS::new();
+        site.build("a", &mut fs).unwrap();
+        site.build("b", &mut fs).unwrap();
+        let read = |fs: &rsfs::mem::FS, path: &str| {
+            let mut text = String::new();
+            fs.open_file(site.root.join(".public").join(path)).unwrap().read_to_string(&mut text).unwrap();
+            text
+        };
+        for target in ["a", "b"] {
+            let page = read(&fs, &format!("{}/index.html", target));
+            let src = page.split("src=\"/").nth(1).and_then(|rest| rest.split('"').next()).unwrap();
+            assert!(src.starts_with("static/components/card/card."), "{}", page);
+            assert_eq!(read(&fs, src), format!("console.log('{}');", target));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::ops::Range;
+use std::path::Path;
+use minijinja::{Error, State, Value};
+use serde::de::Error as _;
+use crate::build::renderer_state::{get_state, lock_state};
+use crate::build::themes::{data_roots, find_file};
+use crate::util::diagnostic::Diagnostic;
+use crate::util::error_mappers::map_io_error;
+
+/// Extensions of files that `blocks()` renders as data blocks, `data()` also reads `.csv`.
+pub const DATA_BLOCK_EXTENSIONS: &[&str] = &["toml", "json", "yaml", "yml"];
+
+/// Byte range of a 1-based line and column, for parsers that only report positions.
+fn line_span(text: &str, line: usize, column: usize) -> Range<usize> {
+    let start = text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum::<usize>() + column.saturating_sub(1);
+    start..start + 1
+}
+
+fn invalid(path: &Path, text: &str, format: &str, message: impl std::fmt::Display, span: Option<Range<usize>>) -> Error {
+    let diagnostic = Diagnostic::new(path, format!("Invalid {} in data file: {}", format, message));
+    match span {
+        Some(span) => diagnostic.with_span(text, span),
+        None => diagnostic,
+    }.into()
+}
+
+/// Every row becomes a map from the header names to the cell text.
+fn parse_csv(path: &Path, text: &str) -> Result<Value, Error> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| invalid(path, text, "CSV", &e, None))?.clone();
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            let span = e.position().map(|position| position.byte() as usize..position.byte() as usize + 1);
+            invalid(path, text, "CSV", &e, span)
+        })?;
+        rows.push(Value::from_iter(headers.iter().zip(record.iter()).map(|(header, cell)| (header.to_string(), cell.to_string()))));
+    }
+    Ok(Value::from(rows))
+}
+
+/// Parses a `.toml`, `.json`, `.yaml`/`.yml` or `.csv` file by its extension.
+pub fn read_data_file(path: &Path) -> Result<Value, Error> {
+    let text = std::fs::read_to_string(path).map_err(map_io_error)?;
+    match path.extension().and_then(|ext| ext.to_str()).unwrap_or_default() {
+        "toml" => toml::from_str::<toml::Table>(&text)
+            .map(|table| Value::from_serialize(&table))
+            .map_err(|e| invalid(path, &text, "TOML", e.message(), e.span())),
+        "json" => serde_json::from_str::<serde_json::Value>(&text)
+            .map(|value| Value::from_serialize(&value))
+            .map_err(|e| invalid(path, &text, "JSON", &e, Some(line_span(&text, e.line(), e.column())))),
+        "yaml" | "yml" => serde_yaml_ng::from_str::<serde_yaml_ng::Value>(&text)
+            .map(|value| Value::from_serialize(&value))
+            .map_err(|e| invalid(path, &text, "YAML", &e, e.location().map(|l| l.index()..l.index() + 1))),
+        "csv" => parse_csv(path, &text),
+        ext => Err(Error::custom(format!("Unsupported data file `{}`, `{}` is not toml, json, yaml or csv", path.display(), ext))),
+    }
+}
+
+/// `{% for member in data("team.csv") %}`, loads a file from `build.data_path` or the `data` directory of a theme.
+pub fn data(state: &State, path: String) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let roots = data_roots(&lock_state(&renderer_state)?.config);
+    let file = find_file(&roots, &path)
+        .ok_or_else(|| Error::custom(format!("Data file `{}` not found in `{}` or a theme", path, roots[0].display())))?;
+    read_data_file(&file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_data_file() {
+        let dir = std::env::temp_dir().join(format!("ressg-data-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let read = |name: &str, text: &str| {
+            std::fs::write(dir.join(name), text).unwrap();
+            read_data_file(&dir.join(name))
+        };
+        let csv = read("team.csv", "name,role\nAnn,dev\nBob,ops\n").unwrap();
+        assert_eq!(csv.get_item(&Value::from(1)).unwrap().get_attr("role").unwrap().to_string(), "ops");
+        assert_eq!(read("a.json", r#"{"plans": [1, 2]}"#).unwrap().get_attr("plans").unwrap().len(), Some(2));
+        assert_eq!(read("a.yaml", "title: Pricing\n").unwrap().get_attr("title").unwrap().to_string(), "Pricing");
+        assert_eq!(read("a.toml", "price = 5\n").unwrap().get_attr("price").unwrap().as_i64(), Some(5));
+        let err = read("b.json", "{\n  \"a\": ,\n}").unwrap_err().to_string();
+        assert!(err.contains("b.json:2:8"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use minijinja::{Error, HtmlEscape, Value};
+use serde::de::Error as _;
+use crate::util::md_parser::is_html;
+use crate::util::html::{add_class, create_element, parse_html_fragment, remove_attr, remove_class, select_nodes, serialize_html_fragment, serialize_html_node, set_attr, top_elements, truncate_html as truncate_nodes, HTML};
+
+/// Elements matching `selector` anywhere in the fragment, or its top level elements.
+fn targets(html: &HTML, selector: Option<String>) -> Result<Vec<HTML>, Error> {
+    match selector {
+        Some(selector) => select_nodes(html, &selector).map_err(Error::custom),
+        None => Ok(top_elements(html)),
+    }
+}
+
+/// Parses safe strings and html sections, any other value is escaped first so it stays text.
+fn fragment(value: &Value) -> HTML {
+    let text = value.to_string();
+    match is_html(value) {
+        true => parse_html_fragment(text),
+        false => parse_html_fragment(HtmlEscape(&text).to_string()),
+    }
+}
+
+fn serialize(html: HTML) -> Result<Value, Error> {
+    Ok(Value::from_safe_string(serialize_html_fragment(html).map_err(Error::custom)?))
+}
+
+pub fn set_attr_filter(value: Value, name: String, attr_value: String, selector: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    for element in targets(&html, selector)? {
+        set_attr(&element, &name, &attr_value);
+    }
+    serialize(html)
+}
+
+pub fn remove_attr_filter(value: Value, name: String, selector: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    for element in targets(&html, selector)? {
+        remove_attr(&element, &name);
+    }
+    serialize(html)
+}
+
+pub fn add_class_filter(value: Value, classes: String, selector: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    for element in targets(&html, selector)? {
+        add_class(&element, &classes);
+    }
+    serialize(html)
+}
+
+pub fn remove_class_filter(value: Value, classes: String, selector: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    for element in targets(&html, selector)? {
+        remove_class(&element, &classes);
+    }
+    serialize(html)
+}
+
+/// Puts the whole fragment into a new `tag` element.
+pub fn wrap(value: Value, tag: String, class: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    let wrapper = create_element(tag, class.map(|c| vec![("class".to_string(), Some(c))]).unwrap_or_default());
+    for child in html.children().collect::<Vec<_>>() {
+        wrapper.append(child);
+    }
+    html.append(wrapper);
+    serialize(html)
+}
+
+/// Outer html of every element matching `selector`.
+pub fn select(value: Value, selector: String) -> Result<Value, Error> {
+    let html = fragment(&value);
+    let mut result = String::new();
+    for element in select_nodes(&html, &selector).map_err(Error::custom)? {
+        result.push_str(&serialize_html_node(&element).map_err(Error::custom)?);
+    }
+    Ok(Value::from_safe_string(result))
+}
+
+/// Plain text of the fragment, it is escaped again on output when auto-escaping is on.
+pub fn strip_tags(value: String) -> Value {
+    Value::from(parse_html_fragment(value).text_contents())
+}
+
+/// Text of the first element matching `selector`, empty if nothing matches.
+pub fn query_text(value: String, selector: String) -> Result<Value, Error> {
+    let html = parse_html_fragment(value);
+    let text = select_nodes(&html, &selector).map_err(Error::custom)?
+        .first()
+        .map(|element| element.text_contents())
+        .unwrap_or_default();
+    Ok(Value::from(text))
+}
+
+pub fn truncate_html(value: Value, chars: usize, end: Option<String>) -> Result<Value, Error> {
+    let html = fragment(&value);
+    truncate_nodes(&html, chars, end.as_deref().unwrap_or("…"));
+    serialize(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let html = r#"<p class="a b">Text <img src="x.webp"></p><p>More</p>"#.to_string();
+        let safe = Value::from_safe_string(html.clone());
+        assert_eq!(set_attr_filter(safe.clone(), "loading".into(), "lazy".into(), Some("img".into())).unwrap().to_string(),
+                   r#"<p class="a b">Text <img src="x.webp" loading="lazy"></p><p>More</p>"#);
+        assert_eq!(remove_class_filter(safe.clone(), "a".into(), None).unwrap().to_string(),
+                   r#"<p class="b">Text <img src="x.webp"></p><p>More</p>"#);
+        assert_eq!(wrap(safe.clone(), "section".into(), Some("s".into())).unwrap().to_string(),
+                   format!(r#"<section class="s">{}</section>"#, html));
+        assert_eq!(select(safe, "img".into()).unwrap().to_string(), r#"<img src="x.webp">"#);
+        assert_eq!(strip_tags(html.clone()).to_string(), "Text More");
+        assert_eq!(query_text(html, "p + p".into()).unwrap().to_string(), "More");
+    }
+
+    #[test]
+    fn test_unsafe_input_stays_text() {
+        let value = Value::from("<script>alert(1)</script>");
+        let escaped = "&lt;script&gt;alert(1)&lt;/script&gt;";
+        let outputs = [
+            set_attr_filter(value.clone(), "a".into(), "b".into(), None).unwrap(),
+            remove_attr_filter(value.clone(), "a".into(), None).unwrap(),
+            add_class_filter(value.clone(), "a".into(), None).unwrap(),
+            remove_class_filter(value.clone(), "a".into(), None).unwrap(),
+            truncate_html(value.clone(), 100, None).unwrap(),
+        ];
+        for output in outputs {
+            assert_eq!(output.to_string(), escaped);
+        }
+        assert_eq!(wrap(value.clone(), "h2".into(), None).unwrap().to_string(), format!("<h2>{}</h2>", escaped));
+        assert_eq!(select(value, "script".into()).unwrap().to_string(), "");
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+use base64::prelude::*;
+use markup5ever::ns;
+use minijinja::value::Kwargs;
+use minijinja::{Error, State, Value};
+use serde::de::Error as _;
+use crate::build::renderer_state::{get_state, lock_state};
+use crate::build::themes::{find_file, static_roots};
+use crate::util::html::{add_class, create_element, append_text, parse_html_fragment, select_nodes, serialize_html_fragment, set_attr, HTML};
+
+/// Files bigger than this are not embedded by `data_uri` unless `max_bytes` is given.
+pub const DATA_URI_LIMIT: usize = 8 * 1024;
+
+fn read_static(state: &State, path: &str) -> Result<(PathBuf, Vec<u8>), Error> {
+    let renderer_state = get_state(state)?;
+    let roots = static_roots(&lock_state(&renderer_state)?.config);
+    let file = find_file(&roots, path).unwrap_or_else(|| roots[0].join(path));
+    let bytes = std::fs::read(&file).map_err(|e| Error::custom(format!("Can not read `{}`: {}", file.display(), e)))?;
+    Ok((file, bytes))
+}
+
+/// Svg elements kept by `svg()`, everything else is dropped with its content.
+/// Scripting (`script`, `foreignObject`), styles and animations (`animate`, `set`, ...) are left out on purpose.
+const SVG_ELEMENTS: &[&str] = &[
+    "svg", "g", "defs", "symbol", "use", "title", "desc", "a", "image", "switch",
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon", "text", "tspan", "textPath",
+    "linearGradient", "radialGradient", "stop", "pattern", "clipPath", "mask", "marker",
+    "filter", "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite", "feDropShadow", "feFlood",
+    "feFuncA", "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur", "feMerge", "feMergeNode", "feMorphology", "feOffset",
+];
+
+/// Presentation and geometry attributes kept by `svg()`, plus `aria-*`. Event handlers and `style` are dropped.
+const SVG_ATTRIBUTES: &[&str] = &[
+    "id", "class", "lang", "role", "focusable", "tabindex", "xmlns", "version", "viewBox", "preserveAspectRatio",
+    "x", "y", "x1", "y1", "x2", "y2", "cx", "cy", "r", "rx", "ry", "fx", "fy", "fr", "dx", "dy", "d", "points",
+    "width", "height", "transform", "pathLength", "rotate", "textLength", "lengthAdjust", "startOffset",
+    "fill", "fill-opacity", "fill-rule", "clip-rule", "clip-path", "mask", "filter", "opacity", "color", "display", "visibility",
+    "stroke", "stroke-width", "stroke-linecap", "stroke-linejoin", "stroke-miterlimit", "stroke-dasharray", "stroke-dashoffset", "stroke-opacity",
+    "marker-start", "marker-mid", "marker-end", "markerWidth", "markerHeight", "markerUnits", "refX", "refY", "orient",
+    "offset", "stop-color", "stop-opacity", "gradientUnits", "gradientTransform", "spreadMethod",
+    "patternUnits", "patternContentUnits", "patternTransform", "maskUnits", "maskContentUnits", "clipPathUnits",
+    "filterUnits", "primitiveUnits", "in", "in2", "result", "stdDeviation", "mode", "operator", "k1", "k2", "k3", "k4",
+    "values", "type", "tableValues", "slope", "intercept", "amplitude", "exponent", "radius", "flood-color", "flood-opacity",
+    "font-family", "font-size", "font-style", "font-weight", "letter-spacing", "text-anchor", "dominant-baseline",
+    "shape-rendering", "vector-effect", "space", "href",
+];
+
+/// `href` schemes kept on links, `use` and `image`, besides relative urls and `#fragment` references.
+const SVG_URL_SCHEMES: &[&str] = &["http:", "https:", "mailto:", "data:image/png", "data:image/jpeg", "data:image/gif", "data:image/webp"];
+
+/// Browsers skip whitespace and control characters in urls, so `java\tscript:` has to be checked without them.
+fn safe_svg_url(value: &str) -> bool {
+    let url = value.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect::<String>().to_lowercase();
+    match url.find([':', '/', '?', '#']) {
+        Some(index) if url[index..].starts_with(':') => SVG_URL_SCHEMES.iter().any(|scheme| url.starts_with(scheme)),
+        _ => true,
+    }
+}
+
+/// Keeps only allowlisted svg elements and attributes, text and `href`s with safe urls.
+fn sanitize_svg(root: &HTML) {
+    let nodes = root.inclusive_descendants().collect::<Vec<_>>();
+    for node in nodes {
+        let Some(el) = node.as_element() else {
+            if node.as_text().is_none() {
+                node.detach();
+            }
+            continue;
+        };
+        if el.name.ns != ns!(svg) || !SVG_ELEMENTS.contains(&el.name.local.as_ref()) {
+            node.detach();
+            continue;
+        }
+        el.attributes.borrow_mut().map.retain(|name, attr| {
+            let known_ns = name.ns == ns!() || name.ns == ns!(xlink) || name.ns == ns!(xml) || name.ns == ns!(xmlns);
+            let allowed = SVG_ATTRIBUTES.contains(&name.local.as_ref()) || name.local.starts_with("aria-");
+            known_ns && allowed && (name.local.as_ref() != "href" || safe_svg_url(&attr.value))
+        });
+    }
+}
+
+/// Inlines an svg from `static_path`. `class` is added to the root element, `title` becomes
+/// its accessible name, otherwise the svg is hidden from assistive technology.
+pub fn svg(state: &State, path: String, kwargs: Kwargs) -> Result<Value, Error> {
+    let class = kwargs.get::<Option<String>>("class")?;
+    let title = kwargs.get::<Option<String>>("title")?;
+    kwargs.assert_all_used()?;
+    let (file, bytes) = read_static(state, &path)?;
+
+    let fragment = parse_html_fragment(String::from_utf8_lossy(&bytes).to_string());
+    let root = select_nodes(&fragment, "svg").map_err(Error::custom)?.into_iter().next()
+        .ok_or_else(|| Error::custom(format!("`{}` has no <svg> element", file.display())))?;
+    for child in fragment.children().collect::<Vec<_>>() {
+        if child != root {
+            child.detach();
+        }
+    }
+    sanitize_svg(&root);
+
+    if let Some(class) = class {
+        add_class(&root, &class);
+    }
+    match title {
+        Some(title) => {
+            set_attr(&root, "role", "img");
+            let mut element = create_element("title".to_string(), vec![]);
+            append_text(&mut element, title);
+            root.prepend(element);
+        }
+        None => set_attr(&root, "aria-hidden", "true"),
+    }
+    Ok(Value::from_safe_string(serialize_html_fragment(fragment).map_err(Error::custom)?))
+}
+
+/// Base64 `data:` url of a file from `static_path`, for files up to `max_bytes` (8 KiB by default).
+pub fn data_uri(state: &State, path: String, kwargs: Kwargs) -> Result<Value, Error> {
+    let max_bytes = kwargs.get::<Option<usize>>("max_bytes")?.unwrap_or(DATA_URI_LIMIT);
+    kwargs.assert_all_used()?;
+    let (file, bytes) = read_static(state, &path)?;
+    if bytes.len() > max_bytes {
+        return Err(Error::custom(format!(
+            "`{}` is {} bytes, more than the {} bytes allowed for data uris, link it with `static()` instead",
+            file.display(), bytes.len(), max_bytes
+        )));
+    }
+    let mime = mime_guess::from_path(&file).first_or_octet_stream();
+    Ok(Value::from_safe_string(format!("data:{};base64,{}", mime, BASE64_STANDARD.encode(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_svg() {
+        let fragment = parse_html_fragment(r#"<svg onload="x()"><!-- c --><script>x()</script><a xlink:href="javascript:x()"><path d="M0"/></a></svg>"#.to_string());
+        sanitize_svg(&select_nodes(&fragment, "svg").unwrap()[0]);
+        assert_eq!(serialize_html_fragment(fragment).unwrap(), r#"<svg><a><path d="M0"></path></a></svg>"#);
+    }
+
+    #[test]
+    fn test_sanitize_svg_allowlist() {
+        let fragment = parse_html_fragment(concat!(
+            r#"<svg viewBox="0 0 1 1"><style>*{}</style><foreignObject><p>x</p></foreignObject>"#,
+            r#"<a href="javascript:x()"><animate attributeName="href" to="javascript:x()"/><set attributeName="href" to="javascript:x()"/></a>"#,
+            "<a href=\"\u{1}java&#x09;script:x()\"></a><a href=\" JAVASCRIPT:x()\"></a>",
+            r##"<use xlink:href="#icon" style="fill:red"/><a href="https://example.org"></a></svg>"##,
+        ).to_string());
+        sanitize_svg(&select_nodes(&fragment, "svg").unwrap()[0]);
+        assert_eq!(
+            serialize_html_fragment(fragment).unwrap(),
+            r##"<svg viewBox="0 0 1 1"><a></a><a></a><a></a><use xlink:href="#icon"></use><a href="https://example.org"></a></svg>"##,
+        );
+    }
+}
@@ -2,8 +2,20 @@ mod blocks;
 mod static_ref;
 mod try_add_class;
 mod sass;
+mod page_assets;
+mod component;
+mod inline;
+mod html_filters;
+mod std_filters;
+mod data;
 
-pub use blocks::blocks;
-pub use static_ref::static_ref;
+pub use blocks::{blocks, collect_blocks};
+pub use static_ref::{hashed_static_ref, integrity, script, static_ref};
 pub use try_add_class::try_add_class;
-pub use sass::{include_sass, sass, SassState};
\ No newline at end of file
+pub use component::component;
+pub use inline::{data_uri, svg};
+pub use data::data;
+pub use std_filters::{absolute_url, date, markdown, reading_time, slugify, word_count};
+pub use html_filters::{add_class_filter, query_text, remove_attr_filter, remove_class_filter, select, set_attr_filter, strip_tags, truncate_html, wrap};
+pub use page_assets::{require_css, require_js, scripts_slot, styles_slot, PageAssets};
+pub use sass::{grass_options, include_sass, sass, SassCache, SassState};
\ No newline at end of file
@@ -0,0 +1,75 @@
+use itertools::Itertools;
+use minijinja::{Error, State, Value};
+use crate::build::custom_functions::static_ref::resolve_static_ref;
+use crate::build::deferred::defer;
+use crate::build::renderer_state::{get_state, lock_state};
+
+/// Scripts and stylesheets requested by the templates of one page, in order of the first request.
+#[derive(Default, Clone, Debug)]
+pub struct PageAssets {
+    scripts: Vec<String>,
+    styles: Vec<String>,
+}
+
+impl PageAssets {
+    pub fn add_script(&mut self, url: String) {
+        if !self.scripts.contains(&url) {
+            self.scripts.push(url);
+        }
+    }
+
+    pub fn add_style(&mut self, url: String) {
+        if !self.styles.contains(&url) {
+            self.styles.push(url);
+        }
+    }
+}
+
+pub fn require_js(state: &State, file: String) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let mut locked_state = lock_state(&renderer_state)?;
+    let url = resolve_static_ref(&locked_state.config, &locked_state.static_hashes, &file);
+    locked_state.page_assets.add_script(url);
+    Ok(Value::from_bytes(vec![]))
+}
+
+pub fn require_css(state: &State, file: String) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let mut locked_state = lock_state(&renderer_state)?;
+    let url = resolve_static_ref(&locked_state.config, &locked_state.static_hashes, &file);
+    locked_state.page_assets.add_style(url);
+    Ok(Value::from_bytes(vec![]))
+}
+
+pub fn scripts_slot(state: &State) -> Result<Value, Error> {
+    defer(state, |s| {
+        Ok(s.page_assets.scripts.iter().map(|url| format!("<script src=\"{}\" defer></script>", url)).join("\n"))
+    })
+}
+
+/// Links the page sass bundle (when `sass()` was called) followed by every `require_css` stylesheet.
+pub fn styles_slot(state: &State) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    lock_state(&renderer_state)?.requested_sass.request();
+
+    defer(state, |s| {
+        let bundle = s.requested_sass.bundle_url(&s.config).map(|p| p.to_string_lossy().to_string());
+        Ok(bundle.iter().chain(s.page_assets.styles.iter())
+            .map(|url| format!("<link rel=\"stylesheet\" href=\"{}\">", url))
+            .join("\n"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedupe_keeps_first_request_order() {
+        let mut assets = PageAssets::default();
+        for url in ["/b.js", "/a.js", "/b.js"] {
+            assets.add_script(url.to_string());
+        }
+        assert_eq!(assets.scripts, vec!["/b.js", "/a.js"]);
+    }
+}
@@ -1,83 +1,184 @@
-use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::path::{PathBuf};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use anyhow::anyhow;
 use itertools::Itertools;
+use minijinja::value::Kwargs;
 use minijinja::{Error, State, Value};
 use rsfs::GenFS;
-use serde::de::Error as _;
-use sha1::{Digest, Sha1};
-use crate::build::renderer_state::{RendererState, _RendererState, get_state, lock_state, RENDERER_STATE};
+use crate::build::deferred::defer;
+use crate::build::renderer_state::{get_state, lock_state, _RendererState};
+use crate::build::css::{finish_css, write_processed};
+use crate::build::static_files::{sha1_hex, sri_hash, FileHash};
+use crate::build::themes::sass_roots;
+use crate::config::{BuildConfig, SassStyle};
 
 pub fn sass(state: &State, source_path: String) -> Result<Value, Error> {
     let renderer_state = get_state(state)?;
     let mut locked_state = lock_state(&renderer_state)?;
-    let sass_state = &mut locked_state.requested_sass.files;
-    sass_state.insert(PathBuf::from(source_path));
+    locked_state.requested_sass.add(PathBuf::from(source_path));
 
     Ok(Value::from_bytes(vec![]))
 }
 
-pub fn include_sass(state: &State) -> Result<Value, Error> {
+pub fn include_sass(state: &State, kwargs: Kwargs) -> Result<Value, Error> {
+    let integrity = kwargs.get::<Option<bool>>("integrity")?.unwrap_or(false);
+    kwargs.assert_all_used()?;
     let renderer_state = get_state(state)?;
-    let mut locked_state = lock_state(&renderer_state)?;
-    locked_state.requested_sass.requested = true;
-    let index_file = locked_state.out_prefix.join(SassState::OUT_NAME);
+    lock_state(&renderer_state)?.requested_sass.request();
+
+    defer(state, move |s| {
+        let (Some(url), Some(bundle)) = (s.requested_sass.bundle_url(&s.config), &s.requested_sass.bundle) else {
+            return Ok(String::new());
+        };
+        Ok(match integrity {
+            true => format!("<link rel=\"stylesheet\" href=\"{}\" integrity=\"{}\" crossorigin=\"anonymous\">", url.display(), bundle.integrity),
+            false => format!("<link rel=\"stylesheet\" href=\"{}\">", url.display()),
+        })
+    })
+}
 
-    let link_elem = format!("<link rel=\"stylesheet\" href=\"{}?hash={}\">", index_file.to_string_lossy(), locked_state.requested_sass.hash);
-    Ok(Value::from_safe_string(link_elem))
+/// Grass options shared by target bundles and standalone stylesheets, the site's `sass.source` is searched before the themes.
+pub fn grass_options(config: &BuildConfig) -> grass::Options<'static> {
+    sass_roots(config).into_iter()
+        .fold(grass::Options::default(), |options, root| options.load_path(root))
+        .style(match config.sass.style {
+            SassStyle::Expanded => grass::OutputStyle::Expanded,
+            SassStyle::Compressed => grass::OutputStyle::Compressed,
+        })
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct SassState{
-    files: HashSet<PathBuf>,
+    /// Sources in the order `sass()` first requested them, which is the cascade order.
+    files: Vec<PathBuf>,
     requested: bool,
-    hash: String,
+    bundle: Option<FileHash>,
+}
+
+/// Compiled bundles of one build, shared by all targets.
+#[derive(Default, Debug)]
+pub struct SassCache {
+    /// Ordered source list to the hashes of its compiled css.
+    bundles: HashMap<Vec<PathBuf>, FileHash>,
+    /// Hashes of written bundles by the sha1 of their compiled css.
+    written: HashMap<String, FileHash>,
 }
 
 impl SassState {
-    const OUT_NAME: &'static str = "index.css";
 
-    pub fn with_hash(hash: Option<String>) -> Self {
-        let mut res: Self = Default::default();
-        if let Some(hash) = hash {
-            res.hash = hash;
+    /// Content-addressed bundle location relative to the output directory.
+    pub fn bundle_file(config: &BuildConfig, hash: &str) -> PathBuf {
+        PathBuf::from(&config.static_output)
+            .join(&config.sass.destination)
+            .join(format!("{}.css", &hash[..hash.len().min(16)]))
+    }
+
+    pub fn add(&mut self, source_path: PathBuf) {
+        if !self.files.contains(&source_path) {
+            self.files.push(source_path);
         }
-        res
     }
-    fn compile_to_string(&self) -> Result<String, anyhow::Error> {
+
+    /// Marks the bundle as linked from the page, so it gets built.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Url of the compiled bundle, `None` if the page requested no sass.
+    pub fn bundle_url(&self, config: &BuildConfig) -> Option<PathBuf> {
+        self.bundle.as_ref().map(|bundle| PathBuf::from(&config.prefix).join(Self::bundle_file(config, &bundle.sha1)))
+    }
+
+    fn compile_to_string(&self, options: &grass::Options) -> Result<String, anyhow::Error> {
         if self.files.is_empty() {
-            Ok(String::new())
-        } else {
-            let mut opts = Default::default();
-            // opts.load_paths("");
-            grass::from_string(
-                format!("@import {};",
-                        self.files.iter().map(|p| format!("'{}'", p.to_string_lossy())).join(", ")
-                ).to_owned(),
-                &opts
-            )
-        }.map_err(
-            |e| {
-                anyhow!(e)
-            }
-        )
+            return Ok(String::new());
+        }
+        grass::from_string(
+            format!("@import {};", self.files.iter().map(|p| format!("'{}'", p.to_string_lossy())).join(", ")),
+            options,
+        ).map_err(|e| anyhow!(e))
     }
 
-    pub fn build<FS: GenFS>(state: &State, dir: &PathBuf, fs: &mut FS) -> Result<Option<String>, anyhow::Error> {
+    /// Compiles the requested bundle unless an earlier target already did, and writes it once per content hash.
+    /// The bundle hashes are stored in the page state for deferred `include_sass` links.
+    pub fn build<FS: GenFS>(state: &State, cache: &mut SassCache, fs: &mut FS) -> Result<(), anyhow::Error> {
         let renderer_state = get_state(state)?;
-        let locked_state = lock_state(&renderer_state)?;
+        let mut locked_state = lock_state(&renderer_state)?;
+        locked_state.requested_sass.bundle = Self::build_bundle(&locked_state, cache, fs)?;
+        Ok(())
+    }
+
+    fn build_bundle<FS: GenFS>(locked_state: &_RendererState, cache: &mut SassCache, fs: &mut FS) -> Result<Option<FileHash>, anyhow::Error> {
         let s = &locked_state.requested_sass;
-        if !s.requested {
+        if !s.requested || s.files.is_empty() {
             return Ok(None);
         }
-        let mut file_writer = fs.create_file(dir.join("index.css").as_path())?;
-        let res = s.compile_to_string()?;
-        let bytes = res.as_bytes();
-
-        let mut hasher = Sha1::new();
-        hasher.update(&bytes);
-        file_writer.write(bytes)?;
-        Ok(Some(hex::encode(hasher.finalize().as_slice())))
+        if let Some(hash) = cache.bundles.get(&s.files) {
+            return Ok(Some(hash.clone()));
+        }
+
+        let config = &locked_state.config;
+        let res = finish_css(&config.css, &PathBuf::from(&config.sass.source), s.compile_to_string(&grass_options(config))?)?;
+        // The file is named by the compiled css, the integrity has to cover the written bytes including the source map comment.
+        let sha1 = sha1_hex(res.code.as_bytes());
+        let hash = match cache.written.get(&sha1) {
+            Some(hash) => hash.clone(),
+            None => {
+                let written = write_processed(&PathBuf::from(&config.output).join(Self::bundle_file(config, &sha1)), res, fs)?;
+                let hash = FileHash { sha1: sha1.clone(), integrity: sri_hash(written.as_bytes()) };
+                cache.written.insert(sha1, hash.clone());
+                hash
+            }
+        };
+        cache.bundles.insert(s.files.clone(), hash.clone());
+        Ok(Some(hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_order_follows_calls() {
+        let dir = std::env::temp_dir().join(format!("ressg-sass-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("_a.scss"), "p { color: red; }").unwrap();
+        std::fs::write(dir.join("_b.scss"), "p { color: blue; }").unwrap();
+        let options = grass::Options::default().load_path(&dir).style(grass::OutputStyle::Compressed);
+
+        let state = SassState { files: vec!["b".into(), "a".into()], requested: true, bundle: None };
+        assert_eq!(state.compile_to_string(&options).unwrap(), "p{color:blue}p{color:red}");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bundle_integrity_covers_source_map_comment() {
+        use std::io::Read;
+        use crate::build::renderer_state::RendererStateParams;
+        let dir = std::env::temp_dir().join(format!("ressg-sass-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("_a.scss"), "p { color: red; }").unwrap();
+        let mut config = BuildConfig::default();
+        config.sass.source = dir.to_string_lossy().to_string();
+        config.css.source_maps = true;
+        let mut state = _RendererState::new(RendererStateParams {
+            config,
+            target_path: dir.clone(),
+            out_dir: PathBuf::new(),
+            out_prefix: PathBuf::new(),
+            static_hashes: Default::default(),
+            target_paths: Default::default(),
+        });
+        state.requested_sass = SassState { files: vec!["a".into()], requested: true, bundle: None };
+
+        let mut fs = rsfs::mem::FS::new();
+        let hash = SassState::build_bundle(&state, &mut SassCache::default(), &mut fs).unwrap().unwrap();
+        let mut written = String::new();
+        fs.open_file(PathBuf::from(&state.config.output).join(SassState::bundle_file(&state.config, &hash.sha1)))
+            .unwrap().read_to_string(&mut written).unwrap();
+        assert!(written.contains("sourceMappingURL="), "{}", written);
+        assert_eq!(hash.integrity, sri_hash(written.as_bytes()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,36 +1,90 @@
 use std::path::PathBuf;
 use std::sync::MutexGuard;
 use anyhow::anyhow;
+use minijinja::value::Kwargs;
 use minijinja::{Error, State, Value};
 use serde::de::Error as _;
 use url::Url;
 use crate::build::renderer_state::{RendererState, _RendererState, get_state, lock_state, RENDERER_STATE};
+use crate::build::static_files::StaticHashes;
+use crate::config::BuildConfig;
 
-pub fn static_ref(state: &State, file: String) -> Result<Value, Error> {
-    if Url::parse(&file).is_ok() {
-        return Ok(Value::from_safe_string(file))
+/// Cache-busted url of a file from `static_path`, `None` if the file was not collected.
+pub fn hashed_static_ref(config: &BuildConfig, static_hashes: &StaticHashes, file: &str) -> Option<String> {
+    let static_file = PathBuf::from(&config.static_output).join(file);
+    let static_ref = PathBuf::from(&config.prefix).join(&static_file);
+    static_hashes.get(&static_file).map(|hash| format!("{}?hash={}", static_ref.display(), hash.sha1))
+}
+
+/// Url of a static file as `static()` renders it: absolute urls are kept, missing files get no hash.
+pub fn resolve_static_ref(config: &BuildConfig, static_hashes: &StaticHashes, file: &str) -> String {
+    if Url::parse(file).is_ok() {
+        return file.to_string();
     }
+    if let Some(hashed) = hashed_static_ref(config, static_hashes, file) {
+        return hashed;
+    }
+    let static_dir = PathBuf::from(&config.static_output);
+
+    let static_file = static_dir.join(file);
+    let static_ref = PathBuf::from(&config.prefix).join(&static_file);
+    log::warn!("Can not find hash for static file {}", static_file.display());
+    format!("{}", static_ref.display())
+}
+
+/// `<script>` tag for a `build.js.entries` bundle.
+pub fn script(state: &State, entry: String) -> Result<Value, Error> {
     let renderer_state = get_state(state)?;
     let locked_state = lock_state(&renderer_state)?;
     let config = &locked_state.config;
-    let static_hashes = &locked_state.static_hashes;
+    if !config.js.entries.contains(&entry) {
+        return Err(Error::custom(format!("`{}` is not listed in `build.js.entries`", entry)));
+    }
+    let file = PathBuf::from(&config.js.destination).join(&entry);
+    let url = resolve_static_ref(config, &locked_state.static_hashes, &file.to_string_lossy());
+    Ok(Value::from_safe_string(format!("<script src=\"{}\" defer></script>", url)))
+}
 
-    let static_dir = PathBuf::from(&config.static_output);
+/// Only the url, so it fits into any attribute, `integrity()` gives the hash for the attribute next to it.
+pub fn static_ref(state: &State, file: String, kwargs: Kwargs) -> Result<Value, Error> {
+    if kwargs.has("integrity") {
+        return Err(Error::custom(format!(
+            "`static()` has no `integrity` argument, use `integrity=\"{{{{ integrity('{}') }}}}\"` next to the url", file,
+        )));
+    }
+    kwargs.assert_all_used()?;
+    let renderer_state = get_state(state)?;
+    let locked_state = lock_state(&renderer_state)?;
+    Ok(Value::from_safe_string(resolve_static_ref(&locked_state.config, &locked_state.static_hashes, &file)))
+}
 
-    let static_file = static_dir.join(file);
-    let static_ref = PathBuf::from(&config.prefix).join(&static_file);
-    Ok(Value::from_safe_string(match static_hashes.get(&static_file) {
-        None => {
-            log::warn!("Can not find hash for static file {}", static_file.display());
-            format!("{}", static_ref.display())
-        }
-        Some(hash) => {
-            format!(
-                "{}?hash={}",
-                static_ref.display(),
-                hash,
-            )
-        }
-    }))
+/// Subresource Integrity value (`sha384-...`) of a static file, for an `integrity` attribute next to `static()`.
+pub fn integrity(state: &State, file: String) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let locked_state = lock_state(&renderer_state)?;
+    let static_file = PathBuf::from(&locked_state.config.static_output).join(&file);
+    let hash = locked_state.static_hashes.get(&static_file).ok_or_else(|| {
+        Error::custom(format!("Can not compute integrity of `{}`, it is not a static file", file))
+    })?;
+    Ok(Value::from_safe_string(hash.integrity.clone()))
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::build::static_files::FileHash;
+    use crate::build::target_renderer::test_site::TestSite;
+
+    #[test]
+    fn test_static_and_integrity() {
+        let mut site = TestSite::new("static-ref", &[("sources/home/index.toml", "path = \"/\"\n")]);
+        let hash = FileHash::new(b"let a = 1;");
+        site.static_hashes.insert("static/app.js".into(), hash.clone());
+        assert_eq!(
+            site.render("home", r#"<script src="{{ static('app.js') }}" integrity="{{ integrity('app.js') }}"></script>"#).unwrap(),
+            format!(r#"<script src="/static/app.js?hash={}" integrity="{}"></script>"#, hash.sha1, hash.integrity),
+        );
+        assert!(site.render("home", "{{ integrity('missing.js') }}").is_err());
+        let err = site.render("home", "{{ static('app.js', integrity=true) }}").unwrap_err().to_string();
+        assert!(err.contains("integrity('app.js')"), "{}", err);
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use minijinja::value::Kwargs;
+use minijinja::{Error, State, Value};
+use serde::de::Error as _;
+use url::Url;
+use crate::build::renderer_state::{get_state, lock_state};
+use crate::util::html::parse_html_fragment;
+use crate::util::md_parser::markdown_options;
+
+/// Field toml uses for datetimes, frontmatter dates reach templates as maps with this key.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Accepts rfc3339 datetimes, `YYYY-MM-DD[ HH:MM[:SS]]`, toml dates and unix timestamps.
+fn parse_date(value: &Value) -> Result<NaiveDateTime, Error> {
+    if let Some(timestamp) = value.as_i64() {
+        return DateTime::from_timestamp(timestamp, 0)
+            .map(|date| date.naive_utc())
+            .ok_or_else(|| Error::custom(format!("Timestamp `{}` is out of range", timestamp)));
+    }
+    let text = match value.get_attr(TOML_DATETIME)?.as_str() {
+        Some(text) => text.to_string(),
+        None => value.as_str().ok_or_else(|| Error::custom(format!("Can not use `{}` as a date", value)))?.to_string(),
+    };
+    if let Ok(date) = DateTime::parse_from_rfc3339(&text) {
+        return Ok(date.with_timezone(&Utc).naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(&text, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+        .ok_or_else(|| Error::custom(format!("Can not parse date `{}`", text)))
+}
+
+/// `{{ config.date | date("%d %B %Y") }}`, the format uses strftime syntax and defaults to `%Y-%m-%d`.
+pub fn date(value: Value, format: Option<String>) -> Result<Value, Error> {
+    let format = format.unwrap_or_else(|| "%Y-%m-%d".to_string());
+    let items = StrftimeItems::new(&format).collect::<Vec<_>>();
+    if items.contains(&Item::Error) {
+        return Err(Error::custom(format!("Invalid date format `{}`", format)));
+    }
+    let date = Utc.from_utc_datetime(&parse_date(&value)?);
+    Ok(Value::from(date.format_with_items(items.into_iter()).to_string()))
+}
+
+pub fn slugify(value: String) -> String {
+    let mut slug = String::with_capacity(value.len());
+    for c in value.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Renders a markdown string with the markdown options of `build.markdown`.
+pub fn markdown(state: &State, value: String) -> Result<Value, Error> {
+    let renderer_state = get_state(state)?;
+    let options = markdown_options(&lock_state(&renderer_state)?.config.markdown);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new_ext(&value, options));
+    Ok(Value::from_safe_string(html))
+}
+
+/// Words of the text, html tags are not counted.
+pub fn word_count(value: String) -> usize {
+    parse_html_fragment(value).text_contents().split_whitespace().count()
+}
+
+/// Minutes needed to read the text, at least one.
+pub fn reading_time(value: String, kwargs: Kwargs) -> Result<usize, Error> {
+    let wpm = kwargs.get::<Option<usize>>("wpm")?.unwrap_or(WORDS_PER_MINUTE).max(1);
+    kwargs.assert_all_used()?;
+    Ok(word_count(value).div_ceil(wpm).max(1))
+}
+
+/// Turns a site path into a full url on `build.base_url`, paths without a leading `/` are put under `prefix`.
+pub fn absolute_url(state: &State, value: String) -> Result<Value, Error> {
+    if Url::parse(&value).is_ok() {
+        return Ok(Value::from(value));
+    }
+    let renderer_state = get_state(state)?;
+    let locked_state = lock_state(&renderer_state)?;
+    let config = &locked_state.config;
+    if config.base_url.is_empty() {
+        return Err(Error::custom(format!("Can not make `{}` absolute, `build.base_url` is not set", value)));
+    }
+    let path = match value.starts_with('/') {
+        true => value,
+        false => format!("{}/{}", config.prefix.trim_end_matches('/'), value),
+    };
+    Ok(Value::from(format!("{}/{}", config.base_url.trim_end_matches('/'), path.trim_start_matches('/'))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_filters() {
+        assert_eq!(slugify("1. About Us — Ünïcode!".to_string()), "1-about-us-ünïcode");
+        assert_eq!(word_count("<p>one <b>two</b></p> three".to_string()), 3);
+        assert_eq!(date(Value::from("2024-03-05T10:00:00+02:00"), Some("%d.%m.%Y %H:%M".to_string())).unwrap().to_string(), "05.03.2024 08:00");
+        let toml_date = Value::from_serialize(toml::from_str::<toml::Table>("d = 2024-03-05").unwrap());
+        assert_eq!(date(toml_date.get_attr("d").unwrap(), None).unwrap().to_string(), "2024-03-05");
+        assert!(date(Value::from("2024-03-05"), Some("%Q".to_string())).is_err());
+    }
+}
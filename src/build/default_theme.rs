@@ -0,0 +1,17 @@
+/// Template used by targets whose `index.toml` has no `template`.
+pub const DEFAULT_TEMPLATE: &str = "default/layout.html";
+/// Template of block groups whose `_index.md` has no `template`.
+pub const DEFAULT_GROUP_TEMPLATE: &str = "default/group.html";
+
+/// Built-in theme served by the template loader after the site and all configured themes,
+/// so a site replaces any of these files by having its own with the same name.
+const TEMPLATES: &[(&str, &str)] = &[
+    (DEFAULT_TEMPLATE, include_str!("default_theme/layout.html")),
+    ("default/block.html", include_str!("default_theme/block.html")),
+    (DEFAULT_GROUP_TEMPLATE, include_str!("default_theme/group.html")),
+    ("default/style.css", include_str!("default_theme/style.css")),
+];
+
+pub fn template(name: &str) -> Option<String> {
+    TEMPLATES.iter().find(|(path, _)| *path == name).map(|(_, source)| source.to_string())
+}
@@ -0,0 +1,78 @@
+use std::fmt::{Debug, Formatter};
+use minijinja::{Error, State, Value};
+use crate::build::renderer_state::{get_state, lock_state, _RendererState};
+
+const PLACEHOLDER_START: &str = "<!--reSsg-deferred:";
+const PLACEHOLDER_END: &str = "-->";
+
+type DeferredFn = Box<dyn FnOnce(&_RendererState) -> anyhow::Result<String> + Send>;
+
+/// Values that can only be computed once the whole page is rendered, in placeholder order.
+#[derive(Default)]
+pub struct Deferred {
+    values: Vec<DeferredFn>,
+}
+
+impl Debug for Deferred {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deferred({})", self.values.len())
+    }
+}
+
+/// Emits a placeholder that is replaced by the result of `f` after the page is rendered,
+/// when everything requested by the template (sass files, scripts, ...) is known.
+pub fn defer<F>(state: &State, f: F) -> Result<Value, Error>
+where
+    F: FnOnce(&_RendererState) -> anyhow::Result<String> + Send + 'static,
+{
+    let renderer_state = get_state(state)?;
+    let mut locked_state = lock_state(&renderer_state)?;
+    let deferred = &mut locked_state.deferred.values;
+    deferred.push(Box::new(f));
+    Ok(Value::from_safe_string(format!("{}{}{}", PLACEHOLDER_START, deferred.len() - 1, PLACEHOLDER_END)))
+}
+
+/// Computes all deferred values of the page and substitutes them into `html`.
+pub fn resolve_deferred(state: &State, html: String) -> anyhow::Result<String> {
+    let renderer_state = get_state(state)?;
+    let mut locked_state = lock_state(&renderer_state)?;
+    let deferred = std::mem::take(&mut locked_state.deferred.values);
+    let values = deferred.into_iter().map(|f| f(&locked_state)).collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(substitute(&html, &values))
+}
+
+fn substitute(html: &str, values: &[String]) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + PLACEHOLDER_START.len()..];
+        let value = after.find(PLACEHOLDER_END)
+            .and_then(|end| after[..end].parse::<usize>().ok().map(|idx| (idx, end)))
+            .and_then(|(idx, end)| values.get(idx).map(|v| (v, end)));
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + PLACEHOLDER_END.len()..];
+            }
+            None => {
+                out.push_str(PLACEHOLDER_START);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let values = vec!["<link>".to_string(), "b".to_string()];
+        let html = format!("<head>{s}0{e}</head><p>{s}1{e}{s}7{e}</p>", s = PLACEHOLDER_START, e = PLACEHOLDER_END);
+        assert_eq!(substitute(&html, &values), format!("<head><link></head><p>b{}7{}</p>", PLACEHOLDER_START, PLACEHOLDER_END));
+    }
+}
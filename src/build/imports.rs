@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::path::Path;
+use itertools::Itertools;
+use crate::build::themes::template_roots;
+use crate::config::BuildConfig;
+
+/// `{% import %}` lines put in front of every loaded template.
+pub struct TemplateImports {
+    prelude: String,
+    modules: HashSet<String>,
+}
+
+/// Parses a `build.templates.imports` entry, `"lib/forms.html as forms"` or `"lib/forms.html"` (imported as `forms`).
+fn parse_import(entry: &str) -> anyhow::Result<(String, String)> {
+    let (path, name) = match entry.split_once(" as ") {
+        Some((path, name)) => (path.trim(), name.trim().to_string()),
+        None => (entry.trim(), file_stem(Path::new(entry.trim()))?),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        anyhow::bail!("Invalid namespace `{}` in template import `{}`", name, entry);
+    }
+    Ok((path.to_string(), name))
+}
+
+fn file_stem(path: &Path) -> anyhow::Result<String> {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Template import `{}` has no file name", path.display()))
+}
+
+impl TemplateImports {
+    /// Every file in `templates.macros` of the site and its themes is imported under its file stem, followed by `templates.imports`.
+    pub fn discover(config: &BuildConfig) -> anyhow::Result<Self> {
+        let mut imports: Vec<(String, String)> = vec![];
+        if !config.templates.macros.is_empty() {
+            for root in template_roots(config) {
+                let macros_dir = root.join(&config.templates.macros);
+                if !macros_dir.is_dir() {
+                    continue;
+                }
+                for entry in std::fs::read_dir(&macros_dir)?.sorted_by_key(|e| e.as_ref().map(|e| e.file_name()).ok()) {
+                    let path = entry?.path();
+                    let name = Path::new(&config.templates.macros).join(path.file_name().unwrap_or_default()).to_string_lossy().to_string();
+                    if path.is_file() && !imports.iter().any(|(known, _)| *known == name) {
+                        imports.push((name, file_stem(&path)?));
+                    }
+                }
+            }
+        }
+        for entry in config.templates.imports.iter() {
+            imports.push(parse_import(entry)?);
+        }
+        Ok(Self::new(imports))
+    }
+
+    fn new(imports: Vec<(String, String)>) -> Self {
+        Self {
+            prelude: imports.iter().map(|(path, name)| format!("{{% import {:?} as {} %}}", path, name)).join(""),
+            modules: imports.into_iter().map(|(path, _)| path).collect(),
+        }
+    }
+
+    /// The prelude is kept on the first line so error line numbers still match the file,
+    /// imported modules do not get it to avoid import cycles.
+    pub fn prelude_for(&self, name: &str) -> &str {
+        if self.modules.contains(name) { "" } else { &self.prelude }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prelude() {
+        let imports = TemplateImports::new(vec![
+            parse_import("macros/ui.html").unwrap(),
+            parse_import("lib/forms.html as f").unwrap(),
+        ]);
+        assert_eq!(imports.prelude_for("page.html"), r#"{% import "macros/ui.html" as ui %}{% import "lib/forms.html" as f %}"#);
+        assert_eq!(imports.prelude_for("lib/forms.html"), "");
+        assert!(parse_import("a.html as my-ns").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail};
+use itertools::Itertools;
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{Declaration, ExportDefaultDeclarationKind, ImportDeclarationSpecifier, Statement};
+use oxc_codegen::{Codegen, CodegenOptions};
+use oxc_minifier::{Minifier, MinifierOptions};
+use oxc_parser::Parser;
+use oxc_span::{GetSpan, SourceType};
+use rsfs::GenFS;
+use crate::config::BuildConfig;
+use crate::util::diagnostic::Diagnostic;
+use crate::util::md_refs::normalize_path;
+
+const RUNTIME: &str = r#"const __cache = {};
+function __require(id) {
+  if (!(id in __cache)) {
+    __modules[id](__cache[id] = {});
+  }
+  return __cache[id];
+}
+function __export(exports, getters) {
+  for (const name in getters) Object.defineProperty(exports, name, { enumerable: true, get: getters[name] });
+}
+function __exportAll(exports, from) {
+  for (const name in from) if (name !== "default" && !(name in exports)) Object.defineProperty(exports, name, { enumerable: true, get: () => from[name] });
+}
+"#;
+
+/// One ES module rewritten into the body of a registry function.
+struct Module {
+    code: String,
+    imports: Vec<String>,
+}
+
+fn parse_error<E: std::fmt::Display>(path: &Path, errors: Vec<E>) -> anyhow::Error {
+    anyhow!("Can not parse `{}`:\n{}", path.display(), errors.iter().map(|e| e.to_string()).join("\n"))
+}
+
+/// Resolves a relative import the way browsers and bundlers do for `.js` files.
+fn resolve_import(source_dir: &Path, importer: &Path, specifier: &str) -> anyhow::Result<PathBuf> {
+    if !(specifier.starts_with("./") || specifier.starts_with("../")) {
+        bail!("Import `{}` in `{}` is not relative, only local modules are bundled", specifier, importer.display());
+    }
+    let id = normalize_path(&importer.parent().unwrap_or(Path::new("")).join(specifier));
+    [id.clone(), PathBuf::from(format!("{}.js", id.display())), id.join("index.js")].into_iter()
+        .find(|candidate| source_dir.join(candidate).is_file())
+        .ok_or_else(|| anyhow!("Import `{}` in `{}` not found in `{}`", specifier, importer.display(), source_dir.display()))
+}
+
+fn require(id: &Path) -> String {
+    format!("__require({:?})", id.to_string_lossy())
+}
+
+fn rewrite_module(source_dir: &Path, id: &Path) -> anyhow::Result<Module> {
+    let text = std::fs::read_to_string(source_dir.join(id))?;
+    let allocator = Allocator::default();
+    let ret = Parser::new(&allocator, &text, SourceType::mjs()).parse();
+    if !ret.errors.is_empty() {
+        return Err(parse_error(id, ret.errors));
+    }
+    if let Some(dynamic) = ret.module_record.dynamic_imports.first() {
+        return Err(Diagnostic::new(id, "Dynamic `import()` is not supported by the bundler")
+            .with_span(&text, dynamic.span.start as usize..dynamic.span.end as usize)
+            .with_hint("use a static `import` or a separate `build.js.entries` item")
+            .into());
+    }
+
+    let mut edits: Vec<(u32, u32, String)> = vec![];
+    let mut exports: BTreeMap<String, String> = BTreeMap::new();
+    let mut imports = vec![];
+    let mut import = |specifier: &str| -> anyhow::Result<PathBuf> {
+        let resolved = resolve_import(source_dir, id, specifier)?;
+        imports.push(resolved.to_string_lossy().to_string());
+        Ok(resolved)
+    };
+
+    for statement in ret.program.body.iter() {
+        match statement {
+            Statement::ImportDeclaration(decl) => {
+                let module = require(&import(&decl.source.value)?);
+                let mut bindings = vec![];
+                let mut replacement = String::new();
+                for specifier in decl.specifiers.iter().flatten() {
+                    match specifier {
+                        ImportDeclarationSpecifier::ImportSpecifier(s) => bindings.push(format!("{:?}: {}", s.imported.name().as_str(), s.local.name)),
+                        ImportDeclarationSpecifier::ImportDefaultSpecifier(s) => bindings.push(format!("default: {}", s.local.name)),
+                        ImportDeclarationSpecifier::ImportNamespaceSpecifier(s) => replacement.push_str(&format!("const {} = {};", s.local.name, module)),
+                    }
+                }
+                if !bindings.is_empty() {
+                    replacement.push_str(&format!("const {{ {} }} = {};", bindings.join(", "), module));
+                } else if replacement.is_empty() {
+                    replacement = format!("{};", module);
+                }
+                edits.push((decl.span.start, decl.span.end, replacement));
+            }
+            Statement::ExportNamedDeclaration(decl) => {
+                if let Some(declaration) = &decl.declaration {
+                    let names = match declaration {
+                        Declaration::VariableDeclaration(v) => v.declarations.iter()
+                            .flat_map(|d| d.id.get_binding_identifiers())
+                            .map(|i| i.name.to_string())
+                            .collect(),
+                        Declaration::FunctionDeclaration(f) => f.id.iter().map(|i| i.name.to_string()).collect(),
+                        Declaration::ClassDeclaration(c) => c.id.iter().map(|i| i.name.to_string()).collect(),
+                        _ => vec![],
+                    };
+                    for name in names {
+                        exports.insert(name.clone(), name);
+                    }
+                    edits.push((decl.span.start, declaration.span().start, String::new()));
+                    continue;
+                }
+                let from = decl.source.as_ref().map(|s| import(&s.value)).transpose()?;
+                for specifier in decl.specifiers.iter() {
+                    let local = specifier.local.name().to_string();
+                    let value = match &from {
+                        Some(from) => format!("{}[{:?}]", require(from), local),
+                        None => local,
+                    };
+                    exports.insert(specifier.exported.name().to_string(), value);
+                }
+                edits.push((decl.span.start, decl.span.end, String::new()));
+            }
+            Statement::ExportDefaultDeclaration(decl) => {
+                let start = decl.declaration.span().start;
+                let named = match &decl.declaration {
+                    ExportDefaultDeclarationKind::FunctionDeclaration(f) => f.id.as_ref().map(|i| i.name.to_string()),
+                    ExportDefaultDeclarationKind::ClassDeclaration(c) => c.id.as_ref().map(|i| i.name.to_string()),
+                    _ => None,
+                };
+                match named {
+                    Some(name) => {
+                        edits.push((decl.span.start, start, String::new()));
+                        exports.insert("default".to_string(), name);
+                    }
+                    None => {
+                        edits.push((decl.span.start, start, "const __default = ".to_string()));
+                        edits.push((decl.declaration.span().end, decl.declaration.span().end, ";".to_string()));
+                        exports.insert("default".to_string(), "__default".to_string());
+                    }
+                }
+            }
+            Statement::ExportAllDeclaration(decl) => {
+                let module = require(&import(&decl.source.value)?);
+                let replacement = match &decl.exported {
+                    Some(name) => {
+                        exports.insert(name.name().to_string(), module);
+                        String::new()
+                    }
+                    None => format!("__exportAll(__exports, {});", module),
+                };
+                edits.push((decl.span.start, decl.span.end, replacement));
+            }
+            _ => {}
+        }
+    }
+
+    let mut code = String::new();
+    if !exports.is_empty() {
+        code.push_str(&format!(
+            "__export(__exports, {{ {} }});\n",
+            exports.iter().map(|(name, value)| format!("{:?}: () => {}", name, value)).join(", ")
+        ));
+    }
+    let mut last = 0usize;
+    for (start, end, replacement) in edits.into_iter().sorted_by_key(|(start, end, _)| (*start, *end)) {
+        code.push_str(&text[last..start as usize]);
+        code.push_str(&replacement);
+        last = end as usize;
+    }
+    code.push_str(&text[last..]);
+    Ok(Module { code, imports })
+}
+
+/// Imports are destructured once when a module runs, so a module in a cycle would see `undefined`.
+fn check_cycles(modules: &BTreeMap<String, Module>, id: &str, stack: &mut Vec<String>, done: &mut BTreeSet<String>) -> anyhow::Result<()> {
+    if done.contains(id) {
+        return Ok(());
+    }
+    if let Some(start) = stack.iter().position(|m| m == id) {
+        bail!("Circular import `{} -> {}`, bundled modules can not import each other", stack[start..].join(" -> "), id);
+    }
+    stack.push(id.to_string());
+    for import in modules[id].imports.iter() {
+        check_cycles(modules, import, stack, done)?;
+    }
+    stack.pop();
+    done.insert(id.to_string());
+    Ok(())
+}
+
+/// Bundles `entry` and the local modules it imports into one classic script.
+pub fn bundle(source_dir: &Path, entry: &Path, minify: bool) -> anyhow::Result<String> {
+    let mut modules = BTreeMap::new();
+    let mut pending = vec![normalize_path(entry)];
+    while let Some(id) = pending.pop() {
+        let key = id.to_string_lossy().to_string();
+        if modules.contains_key(&key) {
+            continue;
+        }
+        let module = rewrite_module(source_dir, &id)?;
+        pending.extend(module.imports.iter().map(PathBuf::from));
+        modules.insert(key, module);
+    }
+    check_cycles(&modules, &normalize_path(entry).to_string_lossy(), &mut vec![], &mut BTreeSet::new())?;
+
+    let mut code = String::from("(() => {\n\"use strict\";\nconst __modules = {\n");
+    for (id, module) in modules.iter() {
+        code.push_str(&format!("{:?}: function (__exports) {{\n{}\n}},\n", id, module.code));
+    }
+    code.push_str("};\n");
+    code.push_str(RUNTIME);
+    code.push_str(&format!("{};\n}})();\n", require(&normalize_path(entry))));
+
+    if minify { minify_script(entry, &code) } else { Ok(code) }
+}
+
+fn minify_script(entry: &Path, code: &str) -> anyhow::Result<String> {
+    let allocator = Allocator::default();
+    let ret = Parser::new(&allocator, code, SourceType::cjs()).parse();
+    if !ret.errors.is_empty() {
+        return Err(parse_error(entry, ret.errors));
+    }
+    let mut program = ret.program;
+    let minified = Minifier::new(MinifierOptions::default()).minify(&allocator, &mut program);
+    Ok(Codegen::new()
+        .with_options(CodegenOptions::minify())
+        .with_scoping(minified.scoping)
+        .build(&program)
+        .code)
+}
+
+/// Writes a bundle for every `build.js.entries` item to `{static_output}/{js.destination}/{entry}`.
+pub fn build_js<FS: GenFS>(config: &BuildConfig, fs: &mut FS) -> anyhow::Result<()> {
+    let source_dir = PathBuf::from(&config.js.source);
+    let out_dir = PathBuf::from(&config.output).join(&config.static_output).join(&config.js.destination);
+    for entry in config.js.entries.iter() {
+        let code = bundle(&source_dir, Path::new(entry), config.js.minify)?;
+        let out_file = out_dir.join(entry);
+        if let Some(parent) = out_file.parent() {
+            fs.create_dir_all(parent)?;
+        }
+        fs.create_file(&out_file)?.write_all(code.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_runs_imports_once() {
+        let dir = std::env::temp_dir().join(format!("ressg-js-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("main.js"), "import { greet } from './lib/greet.js';\nimport * as n from './lib';\nconsole.log(greet(n.name));\n").unwrap();
+        std::fs::write(dir.join("lib/greet.js"), "export default 1;\nexport function greet(x) { return `hi ${x}`; }\n").unwrap();
+        std::fs::write(dir.join("lib/index.js"), "export { greet as hello } from './greet';\nexport const name = 'you';\n").unwrap();
+
+        let code = bundle(&dir, Path::new("main.js"), false).unwrap();
+        assert_eq!(code.matches("function greet").count(), 1);
+        assert!(code.contains("\"lib/index.js\": function"));
+        assert!(!code.contains("import "));
+        assert!(!code.contains("export "));
+        assert!(bundle(&dir, Path::new("main.js"), true).unwrap().len() < code.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bundle_rejects_cycles_and_dynamic_imports() {
+        let dir = std::env::temp_dir().join(format!("ressg-js-cycle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.js"), "import { a } from './a.js';\nconsole.log(a);\n").unwrap();
+        std::fs::write(dir.join("a.js"), "import { b } from './b.js';\nexport const a = 1;\n").unwrap();
+        std::fs::write(dir.join("b.js"), "import { a } from './a.js';\nexport const b = a;\n").unwrap();
+        std::fs::write(dir.join("lazy.js"), "const m = await import('./b.js');\n").unwrap();
+
+        let err = bundle(&dir, Path::new("main.js"), false).unwrap_err().to_string();
+        assert!(err.contains("`a.js -> b.js -> a.js`"), "{}", err);
+        let err = bundle(&dir, Path::new("lazy.js"), false).unwrap_err().to_string();
+        assert!(err.contains("Dynamic `import()`") && err.contains("lazy.js:1:17"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,5 +4,13 @@ mod renderer_state;
 mod target_renderer;
 mod static_files;
 mod target_discovery;
+mod ref_resolver;
+mod css;
+mod deferred;
+mod js;
+mod csp;
+mod imports;
+mod themes;
+mod default_theme;
 
 pub use build::build;
\ No newline at end of file
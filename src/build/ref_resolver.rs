@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+use percent_encoding::percent_decode_str;
+use crate::build::custom_functions::hashed_static_ref;
+use crate::build::renderer_state::RendererState;
+use crate::build::static_files::sha1_hex;
+use crate::util::md_refs::{normalize_path, split_fragment, RefResolver};
+
+fn block_dir(block: &Path) -> &Path {
+    block.parent().unwrap_or(Path::new(""))
+}
+
+fn resolve_local(block: &Path, file: &str) -> PathBuf {
+    normalize_path(&block_dir(block).join(percent_decode_str(file).decode_utf8_lossy().as_ref()))
+}
+
+impl RefResolver for RendererState {
+    fn resolve_image(&self, block: &Path, src: &str) -> Option<String> {
+        let mut state = match self.get() {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("{}", e);
+                return None;
+            }
+        };
+        let (file, query) = split_fragment(src);
+
+        let local = resolve_local(block, file);
+        if local.is_file() {
+            let Ok(rel) = local.strip_prefix(normalize_path(&state.target_path)).map(Path::to_path_buf) else {
+                log::warn!("Image `{}` in `{}` is outside of the target directory `{}`", src, block.display(), state.target_path.display());
+                return None;
+            };
+            let bytes = match std::fs::read(&local) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    log::warn!("Can not read image `{}` referenced in `{}`: {}", local.display(), block.display(), e);
+                    return None;
+                }
+            };
+            let out_file = state.out_prefix.join(&rel);
+            let url = PathBuf::from(&state.config.prefix).join(&out_file);
+            state.requested_assets.insert(out_file, local);
+            return Some(format!("{}?hash={}{}", url.display(), sha1_hex(&bytes), query.replacen('?', "&", 1)));
+        }
+
+        if let Some(url) = hashed_static_ref(&state.config, &state.static_hashes, file) {
+            return Some(format!("{}{}", url, query.replacen('?', "&", 1)));
+        }
+        log::warn!("Image `{}` referenced in `{}` not found in block directory or `{}`", src, block.display(), state.config.static_path);
+        None
+    }
+
+    fn resolve_link(&self, block: &Path, href: &str) -> Option<String> {
+        let state = match self.get() {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("{}", e);
+                return None;
+            }
+        };
+        let (file, fragment) = split_fragment(href);
+
+        let source = resolve_local(block, file);
+        if !source.is_file() {
+            log::warn!("Link `{}` in `{}` points to missing file `{}`", href, block.display(), source.display());
+            return None;
+        }
+        for dir in source.ancestors().skip(1) {
+            if let Some(path) = state.target_paths.get(dir) {
+                let url = PathBuf::from(&state.config.prefix).join(path.trim_start_matches('/'));
+                return Some(format!("{}{}", url.display(), fragment));
+            }
+        }
+        log::warn!("Link `{}` in `{}` points to `{}` which is not rendered by any target", href, block.display(), source.display());
+        None
+    }
+}
@@ -6,7 +6,9 @@ use itertools::Itertools;
 use minijinja::{Error, State};
 use minijinja::value::Object;
 use serde::de::Error as _;
-use crate::build::custom_functions::SassState;
+use crate::build::custom_functions::{PageAssets, SassState};
+use crate::build::deferred::Deferred;
+use crate::build::static_files::StaticHashes;
 use crate::config::BuildConfig;
 
 pub static RENDERER_STATE: &str = "RENDERER_STATE";
@@ -34,8 +36,8 @@ pub struct RendererStateParams {
     pub target_path: PathBuf,
     pub out_dir: PathBuf,
     pub out_prefix: PathBuf,
-    pub sass_hash: Option<String>,
-    pub static_hashes: HashMap<PathBuf, String>,
+    pub static_hashes: StaticHashes,
+    pub target_paths: HashMap<PathBuf, String>,
 }
 
 #[derive(Debug)]
@@ -60,19 +62,27 @@ pub struct _RendererState {
     pub target_path: PathBuf,
     pub out_dir: PathBuf,
     pub out_prefix: PathBuf,
-    pub static_hashes: HashMap<PathBuf, String>,
+    pub static_hashes: StaticHashes,
+    pub target_paths: HashMap<PathBuf, String>,
     pub requested_sass: SassState,
+    pub requested_assets: HashMap<PathBuf, PathBuf>,
+    pub page_assets: PageAssets,
+    pub deferred: Deferred,
 }
 impl _RendererState {
     pub fn new(p: RendererStateParams) -> Self {
-        let RendererStateParams { config, target_path, static_hashes, out_dir, out_prefix, sass_hash } = p;
+        let RendererStateParams { config, target_path, static_hashes, out_dir, out_prefix, target_paths } = p;
         Self {
             config,
             target_path,
             static_hashes,
+            target_paths,
             out_dir,
             out_prefix,
-            requested_sass: SassState::with_hash(sass_hash),
+            requested_sass: SassState::default(),
+            requested_assets: HashMap::new(),
+            page_assets: PageAssets::default(),
+            deferred: Deferred::default(),
         }
     }
 }
\ No newline at end of file
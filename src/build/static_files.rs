@@ -1,30 +1,67 @@
 use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use rsfs::{DirEntry, FileType, GenFS, Metadata};
+use base64::prelude::*;
 use sha1::{Digest, Sha1};
-use crate::build::build;
+use sha2::Sha384;
+use crate::build::css::write_css;
+use crate::build::js::build_js;
+use crate::build::custom_functions::grass_options;
+use crate::build::themes::{sass_roots, static_roots};
 use crate::config::BuildConfig;
 
-fn copy_all<FS: GenFS>(from: &PathBuf, to: &PathBuf, fs: &mut FS) -> anyhow::Result<()> {
+fn copy_all<FS: GenFS>(from: &PathBuf, to: &PathBuf, config: &BuildConfig, fs: &mut FS) -> anyhow::Result<()> {
     if from.is_dir() {
         fs.create_dir_all(&to)?;
         for entry in std::fs::read_dir(from)? {
             let entry = entry?;
-            copy_all(&entry.path(), &to.join(entry.file_name()), fs)?;
+            copy_all(&entry.path(), &to.join(entry.file_name()), config, fs)?;
         }
+    } else if from.is_file() && from.extension().is_some_and(|e| e == "css") && config.css.enabled() {
+        write_css(&config.css, from, to, std::fs::read_to_string(from)?, fs)?;
     } else if from.is_file() {
         let bytes = std::fs::read(from)?;
         let mut f = fs.create_file(to)?;
         f.write_all(&bytes)?;
     } else if from.is_symlink() {
-        copy_all(&std::fs::read_link(from)?, to, fs)?;
+        copy_all(&std::fs::read_link(from)?, to, config, fs)?;
     }
     Ok(())
 }
 
-fn collect_hashes<FS: GenFS>(root: &PathBuf, config: &BuildConfig, fs: &FS) -> anyhow::Result<HashMap<PathBuf, String>> {
-    fn _collect_hashes<FS: GenFS>(map: &mut HashMap<PathBuf, String>, path: &PathBuf, config: &BuildConfig, fs: &FS) -> anyhow::Result<()> {
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize().as_slice())
+}
+
+/// Subresource Integrity value (`sha384-<base64>`) of `bytes`.
+pub fn sri_hash(bytes: &[u8]) -> String {
+    format!("sha384-{}", BASE64_STANDARD.encode(<Sha384 as sha2::Digest>::digest(bytes)))
+}
+
+#[derive(Debug, Clone)]
+pub struct FileHash {
+    /// Hex sha1, used as the cachebusting parameter.
+    pub sha1: String,
+    pub integrity: String,
+}
+
+impl FileHash {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            sha1: sha1_hex(bytes),
+            integrity: sri_hash(bytes),
+        }
+    }
+}
+
+/// Hashes of every file under `static_output`, keyed by the path relative to `output`.
+pub type StaticHashes = HashMap<PathBuf, FileHash>;
+
+fn collect_hashes<FS: GenFS>(root: &PathBuf, config: &BuildConfig, fs: &FS) -> anyhow::Result<StaticHashes> {
+    fn _collect_hashes<FS: GenFS>(map: &mut StaticHashes, path: &PathBuf, config: &BuildConfig, fs: &FS) -> anyhow::Result<()> {
         let meta = fs.metadata(path)?.file_type();
         if meta.is_dir() {
             for entry in fs.read_dir(path)? {
@@ -32,11 +69,9 @@ fn collect_hashes<FS: GenFS>(root: &PathBuf, config: &BuildConfig, fs: &FS) -> a
                 _collect_hashes(map, &entry.path(), config, fs)?;
             }
         } else if meta.is_file() {
-            let mut hasher = Sha1::new();
             let mut bytes = Vec::new();
             fs.open_file(path)?.read_to_end(&mut bytes)?;
-            hasher.update(bytes);
-            map.insert(path.to_path_buf().strip_prefix(&config.output)?.to_path_buf(), hex::encode(hasher.finalize().as_slice()));
+            map.insert(path.to_path_buf().strip_prefix(&config.output)?.to_path_buf(), FileHash::new(&bytes));
         }
 
         Ok(())
@@ -46,9 +81,35 @@ fn collect_hashes<FS: GenFS>(root: &PathBuf, config: &BuildConfig, fs: &FS) -> a
     Ok(hashes)
 }
 
-pub fn build_static<FS: GenFS>(config: &BuildConfig, fs: &mut FS) -> anyhow::Result<HashMap<PathBuf, String>> {
+fn is_sass_entry(path: &Path) -> bool {
+    let partial = path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('_'));
+    let sass = path.extension().is_some_and(|e| e == "scss" || e == "sass");
+    !partial && sass
+}
+
+/// Compiles every non-partial stylesheet in `sass.source` once for the whole site, keeping the directory layout.
+fn build_sass<FS: GenFS>(from: &Path, to: &Path, config: &BuildConfig, fs: &mut FS) -> anyhow::Result<()> {
+    if from.is_dir() {
+        for entry in std::fs::read_dir(from)?.collect::<Result<Vec<_>, _>>()? {
+            build_sass(&entry.path(), &to.join(entry.file_name()), config, fs)?;
+        }
+    } else if is_sass_entry(from) {
+        let css = grass::from_path(from, &grass_options(config)).map_err(|e| anyhow::anyhow!(e))?;
+        write_css(&config.css, from, &to.with_extension("css"), css, fs)?;
+    }
+    Ok(())
+}
+
+pub fn build_static<FS: GenFS>(config: &BuildConfig, fs: &mut FS) -> anyhow::Result<StaticHashes> {
     let static_output = PathBuf::from(&config.output).join(&config.static_output);
-    copy_all(&PathBuf::from(&config.static_path), &static_output, fs)?;
-    // build_sass();
+    fs.create_dir_all(&static_output)?;
+    // Later roots are written first, so the site and earlier themes overwrite their files.
+    for root in static_roots(config).iter().rev() {
+        copy_all(root, &static_output, config, fs)?;
+    }
+    for root in sass_roots(config).iter().rev() {
+        build_sass(root, &static_output.join(&config.sass.destination), config, fs)?;
+    }
+    build_js(config, fs)?;
     Ok(collect_hashes(&static_output, config, fs)?)
 }
\ No newline at end of file
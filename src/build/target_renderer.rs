@@ -1,14 +1,23 @@
 use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
-use crate::config::BuildConfig;
-use minijinja::{context, AutoEscape, Environment, UndefinedBehavior, Value};
+use crate::config::{BuildConfig, TemplatesConfig, UndefinedMode};
+use minijinja::{context, default_auto_escape_callback, AutoEscape, Environment, Value};
 use rsfs::GenFS;
 use serde::{Deserialize, Serialize};
-use crate::build::custom_functions::{blocks, include_sass, sass, try_add_class, SassState};
-use crate::build::custom_functions::static_ref;
-use crate::build::renderer_state::{RendererState, RendererStateParams, RENDERER_STATE};
-use crate::util::md_parser::MdValue;
+use crate::build::custom_functions::{absolute_url, date, markdown, reading_time, slugify, word_count};
+use crate::build::custom_functions::{add_class_filter, query_text, remove_attr_filter, remove_class_filter, select, set_attr_filter, strip_tags, truncate_html, wrap};
+use crate::build::custom_functions::{blocks, collect_blocks, component, data, data_uri, svg, script, include_sass, require_css, require_js, sass, scripts_slot, styles_slot, try_add_class, SassCache, SassState};
+use crate::build::custom_functions::{integrity, static_ref};
+use crate::build::csp::apply_csp;
+use crate::build::default_theme::{self, DEFAULT_TEMPLATE};
+use crate::build::deferred::resolve_deferred;
+use crate::build::imports::TemplateImports;
+use crate::build::themes::{theme_dirs, TEMPLATES_DIR};
+use crate::build::renderer_state::{get_state, lock_state, RendererState, RendererStateParams, RENDERER_STATE};
+use crate::build::static_files::StaticHashes;
+use crate::util::md_parser::{is_html, md_formatter, MdValue};
 
 pub struct BuildTarget {
     pub path: PathBuf,
@@ -18,7 +27,29 @@ pub struct BuildTarget {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TargetConfig {
     pub path: String,
+    /// Base template, the built-in layout renders the markdown blocks of the target dir.
+    #[serde(default = "default_template")]
     pub template: String,
+    /// Available to templates as `target.title`.
+    pub title: Option<String>,
+    /// Overrides `build.templates.undefined` for this target.
+    pub undefined: Option<UndefinedMode>,
+    /// Overrides `build.templates.auto_escape` for this target.
+    pub auto_escape: Option<bool>,
+}
+
+fn default_template() -> String {
+    DEFAULT_TEMPLATE.to_string()
+}
+
+impl TargetConfig {
+    pub fn templates(&self, config: &TemplatesConfig) -> TemplatesConfig {
+        TemplatesConfig {
+            undefined: self.undefined.unwrap_or(config.undefined),
+            auto_escape: self.auto_escape.unwrap_or(config.auto_escape),
+            ..config.clone()
+        }
+    }
 }
 
 impl BuildTarget {
@@ -35,66 +66,208 @@ impl BuildTarget {
     }
 }
 
-pub fn prepare_target_env<'a>(config: &BuildConfig, static_hashes: &HashMap<PathBuf, String>, target: &'a BuildTarget, out_dir: PathBuf, out_prefix: PathBuf, sass_hash: Option<String>) -> anyhow::Result<Environment<'a>> {
+pub fn prepare_target_env<'a>(config: &BuildConfig, static_hashes: &StaticHashes, target_paths: &HashMap<PathBuf, String>, target: &'a BuildTarget, out_dir: PathBuf, out_prefix: PathBuf) -> anyhow::Result<Environment<'a>> {
     fn setup_loader(env: &mut Environment, config: &BuildConfig, target: &BuildTarget) -> anyhow::Result<()> {
         let root_loader = minijinja::path_loader(&config.source);
         let target_loader = minijinja::path_loader(target.dir()?);
+        let theme_loaders = theme_dirs(config, TEMPLATES_DIR).into_iter().map(minijinja::path_loader).collect::<Vec<_>>();
+        let imports = TemplateImports::discover(config)?;
 
         env.set_loader(move |name| {
-            Ok(if name.starts_with("~/") {
-                target_loader(&name[2..])?
-            } else {None}
-                .or(root_loader(name)?)
-                .or(target_loader(name)?)
-            )
+            // The target dir comes first so a target can override a site-wide template, `~/name` only looks there.
+            let mut source = match name.strip_prefix("~/") {
+                Some(name) => target_loader(name)?,
+                None => target_loader(name)?.or(root_loader(name)?),
+            };
+            for theme_loader in theme_loaders.iter() {
+                if source.is_none() {
+                    source = theme_loader(name)?;
+                }
+            }
+            Ok(source.or_else(|| default_theme::template(name)).map(|source| format!("{}{}", imports.prelude_for(name), source)))
         });
         Ok(())
     }
     fn setup_filters(env: &mut Environment, config: &BuildConfig) {
         env.add_filter("try_add_class", try_add_class);
+        env.add_filter("add_class", add_class_filter);
+        env.add_filter("set_attr", set_attr_filter);
+        env.add_filter("remove_attr", remove_attr_filter);
+        env.add_filter("remove_class", remove_class_filter);
+        env.add_filter("wrap", wrap);
+        env.add_filter("select", select);
+        env.add_filter("strip_tags", strip_tags);
+        env.add_filter("truncate_html", truncate_html);
+        env.add_filter("query_text", query_text);
+        env.add_filter("date", date);
+        env.add_filter("slugify", slugify);
+        env.add_filter("markdown", markdown);
+        env.add_filter("word_count", word_count);
+        env.add_filter("reading_time", reading_time);
+        env.add_filter("group_by", minijinja::filters::groupby);
+        env.add_filter("absolute_url", absolute_url);
+        env.add_function("absolute_url", absolute_url);
+        env.add_test("html", |value: Value| is_html(&value));
     }
     fn setup_functions(env: &mut Environment, config: &BuildConfig) {
         env.add_function("blocks", blocks);
+        env.add_function("collect_blocks", collect_blocks);
+        env.add_function("data", data);
         env.add_function("static", static_ref);
+        env.add_function("integrity", integrity);
         env.add_function("sass", sass);
         env.add_function("include_sass", include_sass);
+        env.add_function("require_js", require_js);
+        env.add_function("require_css", require_css);
+        env.add_function("scripts_slot", scripts_slot);
+        env.add_function("styles_slot", styles_slot);
+        env.add_function("component", component);
+        env.add_function("script", script);
+        env.add_function("svg", svg);
+        env.add_function("data_uri", data_uri);
     }
-    fn setup_state(env: &mut Environment, config: &BuildConfig, target: &BuildTarget, out_dir: PathBuf, out_prefix: PathBuf, static_hashes: &HashMap<PathBuf, String>, sass_hash: Option<String>) -> anyhow::Result<()> {
+    fn setup_state(env: &mut Environment, config: &BuildConfig, target: &BuildTarget, out_dir: PathBuf, out_prefix: PathBuf, static_hashes: &StaticHashes, target_paths: &HashMap<PathBuf, String>) -> anyhow::Result<()> {
         env.add_global(RENDERER_STATE, Value::from_object(RendererState::new(RendererStateParams {
             config: config.clone(),
             target_path: target.dir()?.to_path_buf(),
             out_dir,
             out_prefix,
             static_hashes: static_hashes.clone(),
-            sass_hash,
+            target_paths: target_paths.clone(),
         })));
         Ok(())
     }
 
+    let templates = target.config.templates(&config.templates);
     let mut env = Environment::new();
-    env.set_undefined_behavior(UndefinedBehavior::Lenient);
-    env.set_auto_escape_callback(|name| AutoEscape::None);
-    setup_state(&mut env, &config, &target, out_dir, out_prefix, static_hashes, sass_hash)?;
+    env.set_undefined_behavior(templates.undefined.into());
+    if templates.auto_escape {
+        env.set_auto_escape_callback(default_auto_escape_callback);
+        env.set_formatter(md_formatter);
+    } else {
+        env.set_auto_escape_callback(|_| AutoEscape::None);
+    }
+    setup_state(&mut env, &config, &target, out_dir, out_prefix, static_hashes, target_paths)?;
     setup_loader(&mut env, &config, &target)?;
     setup_filters(&mut env, &config);
     setup_functions(&mut env, &config);
     Ok(env)
 }
 
-pub fn build_target<FS: GenFS>(config: &BuildConfig, static_hashes: &HashMap<PathBuf, String>, target: &BuildTarget, fs: &mut FS) -> anyhow::Result<()> {
+fn write_assets<FS: GenFS>(state: &minijinja::State, config: &BuildConfig, fs: &mut FS) -> anyhow::Result<()> {
+    let renderer_state = get_state(state)?;
+    let locked_state = lock_state(&renderer_state)?;
+    for (out_file, source) in locked_state.requested_assets.iter() {
+        let dest = PathBuf::from(&config.output).join(out_file);
+        if let Some(parent) = dest.parent() {
+            fs.create_dir_all(parent)?;
+        }
+        fs.create_file(&dest)?.write_all(&fs::read(source)?)?;
+    }
+    Ok(())
+}
+
+pub fn build_target<FS: GenFS>(config: &BuildConfig, static_hashes: &StaticHashes, target_paths: &HashMap<PathBuf, String>, sass_cache: &mut SassCache, csp_policies: &mut Vec<(String, String)>, target: &BuildTarget, fs: &mut FS) -> anyhow::Result<()> {
     let out_prefix = target.config.path.trim_start_matches("/");
     let dir = PathBuf::from(&config.output).join(out_prefix);
     let index = dir.join("index.html");
     fs.create_dir_all(&dir)?;
 
-    let env = prepare_target_env(&config, &static_hashes, &target, dir.clone(), PathBuf::from(out_prefix), None)?;
+    let env = prepare_target_env(&config, &static_hashes, target_paths, &target, dir.clone(), PathBuf::from(out_prefix))?;
     let template = env.get_template(&target.config.template)?;
-    let ctx = ();
-    let (_, state) = template.render_and_return_state(ctx.clone())?;  // Prerender to collect all deferred values.
-    let sass_hash = SassState::build(&state, &dir, fs)?;
+    let ctx = context! { target => &target.config };
+    let (html, state) = template.render_and_return_state(ctx)?;
+    SassState::build(&state, sass_cache, fs)?;
+    let html = resolve_deferred(&state, html)?;
+    let (html, policy) = apply_csp(&config.csp, html)?;
+    if let Some(policy) = policy {
+        let url = PathBuf::from(&config.prefix).join(out_prefix);
+        csp_policies.push((format!("{}/", url.to_string_lossy().trim_end_matches('/')), policy));
+    }
 
-    let env = prepare_target_env(&config, &static_hashes, &target, dir.clone(), PathBuf::from(out_prefix), sass_hash)?;
-    let template = env.get_template(&target.config.template)?;
-    let state = template.render_to_write(ctx, fs.create_file(index)?)?;
+    fs.create_file(index)?.write_all(html.as_bytes())?;
+    write_assets(&state, config, fs)?;
     Ok(())
-}
\ No newline at end of file
+}
+/// Temporary sites for tests of template functions that need a full environment.
+#[cfg(test)]
+pub mod test_site {
+    use super::*;
+    use crate::config::SassConfig;
+
+    /// Site in a temporary directory with its own config, removed on drop.
+    pub struct TestSite {
+        pub root: PathBuf,
+        pub config: BuildConfig,
+        pub static_hashes: StaticHashes,
+    }
+
+    impl TestSite {
+        /// Writes `files` (path relative to the site root, content), a target needs its `sources/{target}/index.toml`.
+        pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!("ressg-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (path, content) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+            let at = |dir: &str| root.join(dir).to_string_lossy().to_string();
+            let config = BuildConfig {
+                source: at("sources"),
+                output: at(".public"),
+                static_path: at("static"),
+                data_path: at("data"),
+                sass: SassConfig { source: at("sass"), ..Default::default() },
+                ..Default::default()
+            };
+            Self { root, config, static_hashes: StaticHashes::new() }
+        }
+
+        /// Renders `source` as a template of `target`, like the page template of that target.
+        pub fn render(&self, target: &str, source: &str) -> Result<String, minijinja::Error> {
+            let target = BuildTarget::new(self.root.join("sources").join(target).join(&self.config.index_toml_name)).unwrap();
+            let env = prepare_target_env(&self.config, &self.static_hashes, &HashMap::new(), &target, self.root.join(".public"), PathBuf::new()).unwrap();
+            env.render_str(source, context! { target => &target.config })
+        }
+
+        /// Builds `target` into `fs` the way the site build does, including its assets.
+        pub fn build<FS: GenFS>(&self, target: &str, fs: &mut FS) -> anyhow::Result<()> {
+            let target = BuildTarget::new(self.root.join("sources").join(target).join(&self.config.index_toml_name))?;
+            build_target(&self.config, &self.static_hashes, &HashMap::new(), &mut SassCache::default(), &mut vec![], &target, fs)
+        }
+    }
+
+    impl Drop for TestSite {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_site::TestSite;
+
+    #[test]
+    fn test_template_override_order() {
+        let mut site = TestSite::new("template-order", &[
+            ("sources/home/index.toml", "path = \"/\"\n"),
+            ("sources/home/t.html", "target"),
+            ("sources/home/components/c/c.html", "target c"),
+            ("sources/t.html", "site"),
+            ("sources/s.html", "site"),
+            ("sources/components/c/c.html", "site c"),
+            ("theme1/templates/t.html", "theme1"),
+            ("theme1/templates/s.html", "theme1"),
+            ("theme1/templates/h.html", "theme1"),
+            ("theme2/templates/h.html", "theme2"),
+            ("theme2/templates/only.html", "theme2"),
+        ]);
+        site.config.themes = vec![site.root.join("theme1").to_string_lossy().to_string(), site.root.join("theme2").to_string_lossy().to_string()];
+        assert_eq!(
+            site.render("home", r#"{% include "t.html" %}|{% include "s.html" %}|{% include "h.html" %}|{% include "only.html" %}|{{ component("c") }}"#).unwrap(),
+            "target|site|theme1|theme2|target c",
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+use crate::config::BuildConfig;
+
+/// A theme directory has the same parts as a site: templates (like `source`), static files, stylesheets and data files.
+pub const TEMPLATES_DIR: &str = "templates";
+pub const STATIC_DIR: &str = "static";
+pub const SASS_DIR: &str = "sass";
+pub const DATA_DIR: &str = "data";
+
+/// `sub` directory of every configured theme, an earlier theme overrides the later ones.
+pub fn theme_dirs(config: &BuildConfig, sub: &str) -> Vec<PathBuf> {
+    config.themes.iter()
+        .map(|theme| Path::new(theme).join(sub))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+fn with_site(site: &str, config: &BuildConfig, sub: &str) -> Vec<PathBuf> {
+    std::iter::once(PathBuf::from(site)).chain(theme_dirs(config, sub)).collect()
+}
+
+pub fn template_roots(config: &BuildConfig) -> Vec<PathBuf> {
+    with_site(&config.source, config, TEMPLATES_DIR)
+}
+
+pub fn static_roots(config: &BuildConfig) -> Vec<PathBuf> {
+    with_site(&config.static_path, config, STATIC_DIR)
+}
+
+pub fn sass_roots(config: &BuildConfig) -> Vec<PathBuf> {
+    with_site(&config.sass.source, config, SASS_DIR)
+}
+
+pub fn data_roots(config: &BuildConfig) -> Vec<PathBuf> {
+    with_site(&config.data_path, config, DATA_DIR)
+}
+
+/// `file` in the first root that has it.
+pub fn find_file(roots: &[PathBuf], file: impl AsRef<Path>) -> Option<PathBuf> {
+    roots.iter().map(|root| root.join(&file)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::custom_functions::grass_options;
+    use crate::build::target_renderer::test_site::TestSite;
+
+    #[test]
+    fn test_site_overrides_themes() {
+        let mut site = TestSite::new("theme-order", &[
+            ("static/a.txt", "site"),
+            ("theme1/static/a.txt", "theme1"),
+            ("theme1/static/b.txt", "theme1"),
+            ("theme2/static/b.txt", "theme2"),
+            ("theme2/static/c.txt", "theme2"),
+            ("sass/_colors.scss", "$c: red;"),
+            ("theme1/sass/_colors.scss", "$c: blue;"),
+            ("theme1/sass/_size.scss", "$s: 1px;"),
+            ("theme2/sass/_size.scss", "$s: 2px;"),
+        ]);
+        site.config.themes = vec![site.root.join("theme1").to_string_lossy().to_string(), site.root.join("theme2").to_string_lossy().to_string()];
+        let read = |file: &str| std::fs::read_to_string(find_file(&static_roots(&site.config), file).unwrap()).unwrap();
+        assert_eq!([read("a.txt"), read("b.txt"), read("c.txt")], ["site", "theme1", "theme2"]);
+
+        let options = grass_options(&site.config).style(grass::OutputStyle::Compressed);
+        assert_eq!(grass::from_string("@import 'colors', 'size'; p { color: $c; margin: $s; }", &options).unwrap(), "p{color:red;margin:1px}");
+    }
+}
@@ -1,5 +1,6 @@
 use conf::{Conf, ConfContext, ConfSerde, ConfSerdeContext, Error, InitializationStateMachine, InnerError, NextValueProducer, ParsedEnv, Parser, ParserConfig, ProgramOption};
 use conf::lazybuf::LazyBuf;
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 use minijinja::value::Object;
 use partially::Partial;
@@ -10,8 +11,9 @@ pub trait Mergable {
 }
 
 #[derive(Partial)]
-#[partially(derive(Conf, Debug))]
-#[derive(Debug, Clone, Serialize, Deserialize, Conf)]
+#[partially(derive(Conf, Debug), skip_attributes)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Conf)]
+#[serde(default)]
 pub struct reSsgConfig {
     #[partially(as_type = "Option<PartialServerConfig>")]
     #[conf(flatten, long_prefix="server.")]
@@ -30,8 +32,9 @@ impl Mergable for reSsgConfig {
 }
 
 #[derive(Partial)]
-#[partially(derive(Conf, Debug))]
-#[derive(Debug, Clone, Serialize, Deserialize, Conf)]
+#[partially(derive(Conf, Debug), skip_attributes)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Conf)]
+#[serde(default)]
 pub struct ServerConfig {
     #[partially(as_type = "Option<PartialControlConfig>")]
     #[conf(flatten, long_prefix="control.")]
@@ -54,7 +57,9 @@ impl Mergable for ServerConfig {
 
 impl From<PartialServerConfig> for ServerConfig {
     fn from(s: PartialServerConfig) -> Self {
-        todo!()
+        let mut res = Self::default();
+        res.merge(s);
+        res
     }
 }
 
@@ -68,6 +73,15 @@ pub struct EndpointConfig {
     pub interface: String,
 }
 
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            port: "8080".to_string(),
+            interface: "127.0.0.1".to_string(),
+        }
+    }
+}
+
 impl Mergable for EndpointConfig {
     type Partial = PartialEndpointConfig;
     fn merge(&mut self, part: Self::Partial) {
@@ -96,6 +110,12 @@ pub enum ControlConfig {
     Prefix(String),
 }
 
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self::Prefix("/control".to_string())
+    }
+}
+
 impl Mergable for ControlConfig {
     type Partial = PartialControlConfig;
     fn merge(&mut self, part: Self::Partial) {
@@ -172,8 +192,9 @@ impl Conf for ControlConfig {
 }
 
 #[derive(Partial)]
-#[partially(derive(Conf, Debug))]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
 #[derive(Debug, Clone, Serialize, Deserialize, Conf)]
+#[serde(default)]
 pub struct BuildConfig {
     #[arg(long)]
     pub source: String,
@@ -183,13 +204,44 @@ pub struct BuildConfig {
     pub output: String,
     #[arg(long)]
     pub prefix: String,
+    /// Scheme and host the site is served from (`https://example.org`), used by `absolute_url`.
+    #[arg(long)]
+    pub base_url: String,
     #[arg(long)]
     pub static_path: String,
     #[arg(long)]
     pub static_output: String,
+    /// Directory of files loaded by `data(path)`.
+    #[arg(long)]
+    pub data_path: String,
+    /// Theme directories with `templates`, `static` and `sass`, used for files the site does not have.
+    #[conf(repeat, long)]
+    #[partially(omit)]
+    #[serde(default)]
+    pub themes: Vec<String>,
     #[partially(as_type = "Option<PartialSassConfig>")]
     #[conf(flatten, long_prefix="sass.")]
     pub sass: SassConfig,
+    #[partially(as_type = "Option<PartialMarkdownConfig>")]
+    #[conf(flatten, long_prefix="markdown.")]
+    #[serde(default)]
+    pub markdown: MarkdownConfig,
+    #[partially(as_type = "Option<PartialCssConfig>")]
+    #[conf(flatten, long_prefix="css.")]
+    #[serde(default)]
+    pub css: CssConfig,
+    #[partially(as_type = "Option<PartialJsConfig>")]
+    #[conf(flatten, long_prefix="js.")]
+    #[serde(default)]
+    pub js: JsConfig,
+    #[partially(as_type = "Option<PartialCspConfig>")]
+    #[conf(flatten, long_prefix="csp.")]
+    #[serde(default)]
+    pub csp: CspConfig,
+    #[partially(as_type = "Option<PartialTemplatesConfig>")]
+    #[conf(flatten, long_prefix="templates.")]
+    #[serde(default)]
+    pub templates: TemplatesConfig,
 }
 
 impl Mergable for BuildConfig {
@@ -200,28 +252,74 @@ impl Mergable for BuildConfig {
         part.index_toml_name.map(|p| self.index_toml_name = p);
         part.output.map(|p| self.output = p);
         part.prefix.map(|p| self.prefix = p);
+        part.base_url.map(|p| self.base_url = p);
         part.static_path.map(|p| self.static_path = p);
         part.static_output.map(|p| self.static_output = p);
+        part.data_path.map(|p| self.data_path = p);
         part.sass.map(|p| self.sass.merge(p));
+        part.markdown.map(|p| self.markdown.merge(p));
+        part.css.map(|p| self.css.merge(p));
+        part.js.map(|p| self.js.merge(p));
+        part.csp.map(|p| self.csp.merge(p));
+        part.templates.map(|p| self.templates.merge(p));
+    }
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            source: "sources".to_string(),
+            index_toml_name: "index.toml".to_string(),
+            output: ".public".to_string(),
+            prefix: "/".to_string(),
+            base_url: String::new(),
+            static_path: "static".to_string(),
+            static_output: "static".to_string(),
+            data_path: "data".to_string(),
+            themes: vec![],
+            sass: SassConfig::default(),
+            markdown: MarkdownConfig::default(),
+            css: CssConfig::default(),
+            js: JsConfig::default(),
+            csp: CspConfig::default(),
+            templates: TemplatesConfig::default(),
+        }
     }
 }
 
 impl From<PartialBuildConfig> for BuildConfig {
     fn from(value: PartialBuildConfig) -> Self {
-        panic!()
+        let mut res = Self::default();
+        res.merge(value);
+        res
     }
 }
 
 impl Object for BuildConfig {}
 
 #[derive(Partial)]
-#[partially(derive(Conf, Debug))]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
 pub struct SassConfig {
+    /// Load path for `sass()` and directory of standalone stylesheets.
     #[arg(long)]
     pub source: String,
+    /// Output directory for compiled css, relative to `static_output`.
     #[arg(long)]
     pub destination: String,
+    #[arg(long)]
+    pub style: SassStyle,
+}
+
+impl Default for SassConfig {
+    fn default() -> Self {
+        Self {
+            source: "sass".to_string(),
+            destination: "css".to_string(),
+            style: SassStyle::default(),
+        }
+    }
 }
 
 impl Mergable for SassConfig {
@@ -229,10 +327,334 @@ impl Mergable for SassConfig {
     fn merge(&mut self, part: Self::Partial) {
         part.source.map(|p| self.source = p);
         part.destination.map(|p| self.destination = p);
+        part.style.map(|p| self.style = p);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SassStyle {
+    #[default]
+    Expanded,
+    Compressed,
+}
+
+impl FromStr for SassStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expanded" => Ok(Self::Expanded),
+            "compressed" => Ok(Self::Compressed),
+            _ => Err(format!("Unknown sass style `{}`, expected `expanded` or `compressed`", s)),
+        }
     }
 }
 impl From<PartialSassConfig> for SassConfig {
     fn from(value: PartialSassConfig) -> Self {
-        todo!()
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+/// Markdown extensions enabled for block files, any block can override them in a `[markdown]` frontmatter table.
+#[derive(Partial)]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    #[arg(long)]
+    pub tables: bool,
+    #[arg(long)]
+    pub smart_punctuation: bool,
+    #[arg(long)]
+    pub footnotes: bool,
+    #[arg(long)]
+    pub strikethrough: bool,
+    #[arg(long)]
+    pub tasklists: bool,
+    /// GitHub flavoured alerts (`> [!NOTE]`).
+    #[arg(long)]
+    pub alerts: bool,
+    #[arg(long)]
+    pub definition_lists: bool,
+    #[arg(long)]
+    pub math: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            tables: true,
+            smart_punctuation: true,
+            footnotes: false,
+            strikethrough: false,
+            tasklists: false,
+            alerts: false,
+            definition_lists: false,
+            math: false,
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Mergable for MarkdownConfig {
+    type Partial = PartialMarkdownConfig;
+    fn merge(&mut self, part: Self::Partial) {
+        part.tables.map(|p| self.tables = p);
+        part.smart_punctuation.map(|p| self.smart_punctuation = p);
+        part.footnotes.map(|p| self.footnotes = p);
+        part.strikethrough.map(|p| self.strikethrough = p);
+        part.tasklists.map(|p| self.tasklists = p);
+        part.alerts.map(|p| self.alerts = p);
+        part.definition_lists.map(|p| self.definition_lists = p);
+        part.math.map(|p| self.math = p);
+    }
+}
+impl From<PartialMarkdownConfig> for MarkdownConfig {
+    fn from(value: PartialMarkdownConfig) -> Self {
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+
+/// Post-processing applied to compiled sass and to `.css` files in `static_path`.
+#[derive(Partial)]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
+pub struct CssConfig {
+    /// Browserslist query (`"> 0.5%, last 2 versions"`), adds vendor prefixes and lowers newer syntax for it.
+    #[arg(long)]
+    pub targets: String,
+    #[arg(long)]
+    pub minify: bool,
+    /// Writes `.css.map` next to every processed file.
+    #[arg(long)]
+    pub source_maps: bool,
+}
+
+impl CssConfig {
+    pub fn enabled(&self) -> bool {
+        !self.targets.is_empty() || self.minify || self.source_maps
+    }
+}
+
+impl Mergable for CssConfig {
+    type Partial = PartialCssConfig;
+    fn merge(&mut self, part: Self::Partial) {
+        part.targets.map(|p| self.targets = p);
+        part.minify.map(|p| self.minify = p);
+        part.source_maps.map(|p| self.source_maps = p);
+    }
+}
+impl From<PartialCssConfig> for CssConfig {
+    fn from(value: PartialCssConfig) -> Self {
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+
+/// Script entry points bundled with their local imports into `{static_output}/{destination}`.
+#[derive(Partial)]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
+pub struct JsConfig {
+    /// Directory with entry points and the modules they import.
+    #[arg(long)]
+    pub source: String,
+    /// Output directory for bundles, relative to `static_output`.
+    #[arg(long)]
+    pub destination: String,
+    /// Entry points relative to `source`.
+    #[conf(repeat, long)]
+    #[partially(omit)]
+    pub entries: Vec<String>,
+    #[arg(long)]
+    pub minify: bool,
+}
+
+impl Default for JsConfig {
+    fn default() -> Self {
+        Self {
+            source: "js".to_string(),
+            destination: "js".to_string(),
+            entries: vec![],
+            minify: true,
+        }
+    }
+}
+
+impl Mergable for JsConfig {
+    type Partial = PartialJsConfig;
+    fn merge(&mut self, part: Self::Partial) {
+        part.source.map(|p| self.source = p);
+        part.destination.map(|p| self.destination = p);
+        part.minify.map(|p| self.minify = p);
+    }
+}
+impl From<PartialJsConfig> for JsConfig {
+    fn from(value: PartialJsConfig) -> Self {
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+
+/// Content-Security-Policy with hashes of the inline scripts and styles of every page.
+#[derive(Partial)]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
+pub struct CspConfig {
+    #[arg(long)]
+    pub mode: CspMode,
+    /// Base directives, the page hashes are added to their `script-src` and `style-src` or appended as new directives.
+    #[arg(long)]
+    pub policy: String,
+}
+
+impl Default for CspConfig {
+    fn default() -> Self {
+        Self {
+            mode: CspMode::Off,
+            policy: "default-src 'self'; img-src 'self' data:".to_string(),
+        }
+    }
+}
+
+impl Mergable for CspConfig {
+    type Partial = PartialCspConfig;
+    fn merge(&mut self, part: Self::Partial) {
+        part.mode.map(|p| self.mode = p);
+        part.policy.map(|p| self.policy = p);
+    }
+}
+impl From<PartialCspConfig> for CspConfig {
+    fn from(value: PartialCspConfig) -> Self {
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CspMode {
+    #[default]
+    Off,
+    /// `<meta http-equiv>` tag at the start of every page head.
+    Meta,
+    /// `_headers` file in `output` with a policy per page path.
+    Headers,
+}
+
+impl FromStr for CspMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "meta" => Ok(Self::Meta),
+            "headers" => Ok(Self::Headers),
+            _ => Err(format!("Unknown csp mode `{}`, expected `off`, `meta` or `headers`", s)),
+        }
+    }
+}
+
+/// Template engine settings, a target can override them in its `index.toml`.
+#[derive(Partial)]
+#[partially(derive(Conf, Debug, Default, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Conf)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    #[arg(long)]
+    pub undefined: UndefinedMode,
+    /// Escapes output of `.html`, `.htm` and `.xml` templates unless it is marked safe.
+    #[arg(long)]
+    pub auto_escape: bool,
+    /// Directory in `source` whose files are imported into every template under their file stem (`ui.html` as `ui`).
+    #[arg(long)]
+    pub macros: String,
+    /// Additional imports for every template, `"lib/forms.html as forms"`.
+    #[conf(repeat, long)]
+    #[partially(omit)]
+    pub imports: Vec<String>,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        Self {
+            undefined: UndefinedMode::Lenient,
+            auto_escape: true,
+            macros: "macros".to_string(),
+            imports: vec![],
+        }
+    }
+}
+
+impl Mergable for TemplatesConfig {
+    type Partial = PartialTemplatesConfig;
+    fn merge(&mut self, part: Self::Partial) {
+        part.undefined.map(|p| self.undefined = p);
+        part.auto_escape.map(|p| self.auto_escape = p);
+        part.macros.map(|p| self.macros = p);
+    }
+}
+impl From<PartialTemplatesConfig> for TemplatesConfig {
+    fn from(value: PartialTemplatesConfig) -> Self {
+        let mut res = Self::default();
+        res.merge(value);
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UndefinedMode {
+    /// Undefined values render as empty strings.
+    #[default]
+    Lenient,
+    /// Like `lenient`, but attributes of undefined values are undefined too instead of an error.
+    Chainable,
+    /// Any use of an undefined value other than `is defined` fails the build.
+    Strict,
+}
+
+impl From<UndefinedMode> for minijinja::UndefinedBehavior {
+    fn from(value: UndefinedMode) -> Self {
+        match value {
+            UndefinedMode::Lenient => Self::Lenient,
+            UndefinedMode::Chainable => Self::Chainable,
+            UndefinedMode::Strict => Self::Strict,
+        }
+    }
+}
+
+impl FromStr for UndefinedMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lenient" => Ok(Self::Lenient),
+            "chainable" => Ok(Self::Chainable),
+            "strict" => Ok(Self::Strict),
+            _ => Err(format!("Unknown undefined mode `{}`, expected `lenient`, `chainable` or `strict`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let config: reSsgConfig = toml::from_str("[build]\noutput = \"out\"\n[build.sass]\nstyle = \"compressed\"\n").unwrap();
+        assert_eq!(config.build.output, "out");
+        assert_eq!(config.build.source, "sources");
+        assert_eq!(config.build.data_path, "data");
+        assert_eq!(config.build.sass.source, "sass");
+        assert_eq!(config.build.sass.style, SassStyle::Compressed);
+        assert_eq!(BuildConfig::from(PartialBuildConfig::default()).index_toml_name, "index.toml");
+    }
+}
@@ -95,7 +95,11 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config_file = std::env::current_dir()?
         .join("config.toml");
 
-    let mut config: reSsgConfig = toml::from_slice(&std::fs::read(config_file)?)?;
+    // Without a config.toml the defaults and the built-in theme are used.
+    let mut config: reSsgConfig = match config_file.is_file() {
+        true => toml::from_slice(&std::fs::read(config_file)?)?,
+        false => reSsgConfig::default(),
+    };
     match args.command {
         Command::Build(cfg) => {
             config.build.merge(cfg);
@@ -0,0 +1,118 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use minijinja::{Error, ErrorKind};
+
+#[derive(Debug, Clone)]
+struct Span {
+    line: usize,
+    column: usize,
+    excerpt: String,
+    underline: usize,
+}
+
+impl Span {
+    fn new(source: &str, range: Range<usize>) -> Self {
+        let start = floor_char_boundary(source, range.start.min(source.len()));
+        let end = floor_char_boundary(source, range.end.clamp(start, source.len()));
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let excerpt = source[line_start..line_end].trim_end_matches('\r').to_string();
+
+        let column = source[line_start..start].chars().count() + 1;
+        let underline = source[start..end.min(line_end).max(start)].trim_end().chars().count().max(1);
+        Self {
+            line: source[..start].matches('\n').count() + 1,
+            column,
+            excerpt,
+            underline,
+        }
+    }
+}
+
+fn floor_char_boundary(s: &str, mut idx: usize) -> usize {
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// Error pointing into a source file, rendered with a line excerpt and an optional hint.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    path: PathBuf,
+    message: String,
+    span: Option<Span>,
+    hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new<P: AsRef<Path>, S: Into<String>>(path: P, message: S) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            message: message.into(),
+            span: None,
+            hint: None,
+        }
+    }
+
+    pub fn with_span(mut self, source: &str, range: Range<usize>) -> Self {
+        self.span = Some(Span::new(source, range));
+        self
+    }
+
+    pub fn with_hint<S: Into<String>>(mut self, hint: S) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        let Some(span) = &self.span else {
+            write!(f, "  --> {}", self.path.display())?;
+            if let Some(hint) = &self.hint {
+                write!(f, "\n  = hint: {}", hint)?;
+            }
+            return Ok(());
+        };
+        let gutter = " ".repeat(span.line.to_string().len());
+        writeln!(f, "{} --> {}:{}:{}", gutter, self.path.display(), span.line, span.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", span.line, span.excerpt)?;
+        write!(f, "{} | {}{}", gutter, " ".repeat(span.column - 1), "^".repeat(span.underline))?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl From<Diagnostic> for Error {
+    fn from(d: Diagnostic) -> Self {
+        Error::new(ErrorKind::SyntaxError, d.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_span() {
+        let source = "# title\ntext\n## more\n";
+        let d = Diagnostic::new("block.md", "Heading stack underflow")
+            .with_span(source, 13..20)
+            .with_hint("`##` used before any `#`");
+        assert_eq!(d.to_string(), r#"Heading stack underflow
+  --> block.md:3:1
+  |
+3 | ## more
+  | ^^^^^^^
+  = hint: `##` used before any `#`"#);
+    }
+}
@@ -4,6 +4,7 @@ mod on_kuchikikiki {
     use itertools::Itertools;
     use kuchikikiki::{parse_fragment, parse_html, Attribute, ExpandedName, NodeRef};
     use kuchikikiki::traits::TendrilSink;
+    use html5ever::serialize::{SerializeOpts, TraversalScope};
     use markup5ever::{local_name, ns, LocalName, QualName};
 
     pub type HTML = NodeRef;
@@ -22,6 +23,61 @@ mod on_kuchikikiki {
         result.try_into().map_err(Into::into)
     }
 
+    /// Serializes `html` itself, not only its children.
+    pub fn serialize_html_node(html: &HTML) -> anyhow::Result<String> {
+        let mut result = vec![];
+        html5ever::serialize(&mut result, html, SerializeOpts { traversal_scope: TraversalScope::IncludeNode, ..Default::default() })?;
+        result.try_into().map_err(Into::into)
+    }
+
+    /// Keeps the first `chars` characters of text and the elements around them, `end` is appended to the last kept text.
+    /// Whitespace-only text between elements is not counted and never gets `end`.
+    /// Returns `false` if the text was already short enough.
+    pub fn truncate_html(html: &HTML, chars: usize, end: &str) -> bool {
+        let mut remaining = chars;
+        let mut last_kept: Option<HTML> = None;
+        let mut cut = None;
+        for node in html.descendants() {
+            let Some(text) = node.as_text() else {
+                continue;
+            };
+            if text.borrow().trim().is_empty() {
+                continue;
+            }
+            let len = text.borrow().chars().count();
+            if len > remaining {
+                // The budget ended exactly with the previous text, so that one gets `end`.
+                let (node, keep) = match (remaining, last_kept) {
+                    (0, Some(last)) => {
+                        let len = last.as_text().unwrap().borrow().chars().count();
+                        (last, len)
+                    }
+                    _ => (node, remaining),
+                };
+                let text = node.as_text().unwrap();
+                let kept = text.borrow().chars().take(keep).collect::<String>();
+                *text.borrow_mut() = format!("{}{}", kept.trim_end(), end);
+                cut = Some(node);
+                break;
+            }
+            remaining -= len;
+            last_kept = Some(node);
+        }
+        let Some(mut current) = cut else {
+            return false;
+        };
+        loop {
+            while let Some(next) = current.next_sibling() {
+                next.detach();
+            }
+            match current.parent() {
+                Some(parent) if parent != *html => current = parent,
+                _ => break,
+            }
+        }
+        true
+    }
+
     pub fn parse_html_document(value: String) -> HTML {
         parse_html().one(value)
     }
@@ -46,13 +102,15 @@ mod on_kuchikikiki {
         };
     }
 
+    /// Html element with plain attributes, they have no namespace like the attributes the parser creates,
+    /// an `html` namespace would be serialized as `unknown_namespace:name`.
     pub fn create_element(element: String, attrs: Vec<(String, Option<String>)>) -> HTML {
         NodeRef::new_element(
             QualName::new(None, ns!(html), LocalName::from(element)),
             attrs.into_iter().map(|(n, v)| {
                 (
                     ExpandedName {
-                        ns: ns!(html),
+                        ns: ns!(),
                         local: LocalName::from(n),
                     },
                     Attribute {
@@ -68,10 +126,70 @@ mod on_kuchikikiki {
         element.append(NodeRef::new_text(text));
     }
 
+    pub fn set_attr(element: &HTML, name: &str, value: &str) {
+        if let Some(el) = element.as_element() {
+            el.attributes.borrow_mut().insert(name, value.to_string());
+        }
+    }
+
+    /// Top level elements of a fragment.
+    pub fn top_elements(html: &HTML) -> Vec<HTML> {
+        html.children().filter(|child| child.as_element().is_some()).collect()
+    }
+
+    pub fn remove_attr(element: &HTML, name: &str) {
+        if let Some(el) = element.as_element() {
+            el.attributes.borrow_mut().remove(name);
+        }
+    }
+
+    pub fn remove_class(element: &HTML, classes: &str) {
+        if let Some(el) = element.as_element() {
+            let mut attributes = el.attributes.borrow_mut();
+            let Some(existing) = attributes.get("class") else {
+                return;
+            };
+            let removed = classes.split_whitespace().collect::<Vec<_>>();
+            let kept = existing.split_whitespace().filter(|c| !removed.contains(c)).join(" ");
+            match kept.is_empty() {
+                true => attributes.remove("class"),
+                false => attributes.insert("class", kept),
+            };
+        }
+    }
+
+    pub fn add_class(element: &HTML, classes: &str) {
+        if let Some(el) = element.as_element() {
+            let existing = el.attributes.borrow().get("class").unwrap_or("").to_string();
+            let joined = existing.split_whitespace().chain(classes.split_whitespace()).unique().join(" ");
+            el.attributes.borrow_mut().insert("class", joined);
+        }
+    }
+
+    /// Elements under `html` matching a css `selector`, in document order.
+    pub fn select_nodes(html: &HTML, selector: &str) -> anyhow::Result<Vec<HTML>> {
+        Ok(html.select(selector)
+            .map_err(|_| anyhow::anyhow!("Invalid css selector `{}`", selector))?
+            .map(|element| element.as_node().clone())
+            .collect())
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
 
+        #[test]
+        fn test_create_element_attributes() {
+            let dom = parse_html_fragment("<p></p>".to_string());
+            let element = create_element("meta".to_string(), vec![
+                ("name".to_string(), Some("a".to_string())),
+                ("async".to_string(), None),
+            ]);
+            assert_eq!(element.as_element().unwrap().attributes.borrow().get("name"), Some("a"));
+            dom.first_child().unwrap().append(element);
+            assert_eq!(serialize_html_fragment(dom).unwrap(), r#"<p><meta name="a" async=""></p>"#);
+        }
+
         #[test]
         fn test_parse_html_fragment() {
             let s = r#"<div>Some <span id="s">text</span></div><div>Some <span id="s">other text</span></div>"#;
@@ -99,6 +217,27 @@ mod on_kuchikikiki {
             assert_eq!(ser, s);
         }
 
+        #[test]
+        fn test_truncate_html() {
+            let dom = parse_html_fragment("<p>Hello <b>brave</b> new</p><p>world</p>".to_string());
+            assert!(truncate_html(&dom, 8, "…"));
+            assert_eq!(serialize_html_fragment(dom).unwrap(), "<p>Hello <b>br…</b></p>");
+
+            let dom = parse_html_fragment("<p>Hi</p>".to_string());
+            assert!(!truncate_html(&dom, 8, "…"));
+
+            let dom = parse_html_fragment("<p>Hello</p><p>world</p>".to_string());
+            assert!(truncate_html(&dom, 5, "…"));
+            assert_eq!(serialize_html_fragment(dom).unwrap(), "<p>Hello…</p>");
+
+            let dom = parse_html_fragment("<p>Hello</p>\n<p>world</p>\n".to_string());
+            assert!(truncate_html(&dom, 5, "…"));
+            assert_eq!(serialize_html_fragment(dom).unwrap(), "<p>Hello…</p>");
+
+            let dom = parse_html_fragment("<p>Hello</p>\n".to_string());
+            assert!(!truncate_html(&dom, 5, "…"));
+        }
+
         #[test]
         fn test_insert_node() {
             let s = r#"<div>Some <span id="s">text</span></div><div>Some <span id="s">other text</span></div>"#;
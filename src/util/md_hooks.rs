@@ -0,0 +1,178 @@
+use minijinja::{context, Environment, Error, ErrorKind, Template, Value};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag};
+
+pub const IMAGE_HOOK: &str = "_hooks/image.html";
+pub const LINK_HOOK: &str = "_hooks/link.html";
+pub const CODEBLOCK_HOOK: &str = "_hooks/codeblock.html";
+pub const CALLOUT_HOOK: &str = "_hooks/callout.html";
+
+/// Site templates that replace pulldown-cmark's default html for selected markdown elements.
+pub struct MdHooks<'a> {
+    image: Option<Template<'a, 'a>>,
+    link: Option<Template<'a, 'a>>,
+    codeblock: Option<Template<'a, 'a>>,
+    callout: Option<Template<'a, 'a>>,
+}
+
+fn lookup<'a>(env: &'a Environment, name: &str) -> Result<Option<Template<'a, 'a>>, Error> {
+    match env.get_template(name) {
+        Ok(t) => Ok(Some(t)),
+        Err(e) if e.kind() == ErrorKind::TemplateNotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn take_inner<'e>(iter: &mut impl Iterator<Item = Event<'e>>) -> Vec<Event<'e>> {
+    let mut depth = 0usize;
+    let mut inner = vec![];
+    for event in iter {
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) if depth == 0 => break,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        inner.push(event);
+    }
+    inner
+}
+
+fn plain_text(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) | Event::DisplayMath(t) => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn to_html<'e>(events: Vec<Event<'e>>) -> Result<String, Error> {
+    let mut html = String::new();
+    pulldown_cmark::html::write_html_fmt(&mut html, events.into_iter())?;
+    Ok(html)
+}
+
+impl<'a> MdHooks<'a> {
+    pub fn load(env: &'a Environment) -> Result<Self, Error> {
+        Ok(Self {
+            image: lookup(env, IMAGE_HOOK)?,
+            link: lookup(env, LINK_HOOK)?,
+            codeblock: lookup(env, CODEBLOCK_HOOK)?,
+            callout: lookup(env, CALLOUT_HOOK)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.image.is_none() && self.link.is_none() && self.codeblock.is_none() && self.callout.is_none()
+    }
+
+    fn hook_for(&self, tag: &Tag) -> Option<&Template<'a, 'a>> {
+        match tag {
+            Tag::Image { .. } => self.image.as_ref(),
+            Tag::Link { .. } => self.link.as_ref(),
+            Tag::CodeBlock(_) => self.codeblock.as_ref(),
+            Tag::BlockQuote(Some(_)) => self.callout.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Replaces every hooked element (start tag, content and end tag) with the html rendered by its hook.
+    pub fn apply<'e>(&self, events: Vec<Event<'e>>) -> Result<Vec<Event<'e>>, Error> {
+        if self.is_empty() {
+            return Ok(events);
+        }
+        let mut out = Vec::with_capacity(events.len());
+        let mut iter = events.into_iter();
+        while let Some(event) = iter.next() {
+            match event {
+                Event::Start(tag) if self.hook_for(&tag).is_some() => {
+                    let inner = take_inner(&mut iter);
+                    out.push(self.render(tag, inner)?);
+                }
+                event => out.push(event),
+            }
+        }
+        Ok(out)
+    }
+
+    fn render<'e>(&self, tag: Tag<'e>, inner: Vec<Event<'e>>) -> Result<Event<'e>, Error> {
+        let Some(hook) = self.hook_for(&tag) else {
+            return Err(Error::new(ErrorKind::InvalidOperation, format!("No markdown hook for {:?}", tag)));
+        };
+        Ok(match tag {
+            Tag::Image { link_type, dest_url, title, id } => Event::InlineHtml(CowStr::from(hook.render(context! {
+                src => dest_url.as_ref(),
+                alt => plain_text(&inner),
+                title => title.as_ref(),
+                id => id.as_ref(),
+                link_type => format!("{:?}", link_type),
+            })?)),
+            Tag::Link { link_type, dest_url, title, id } => Event::InlineHtml(CowStr::from(hook.render(context! {
+                href => dest_url.as_ref(),
+                text => Value::from_safe_string(to_html(self.apply(inner)?)?),
+                title => title.as_ref(),
+                id => id.as_ref(),
+                link_type => format!("{:?}", link_type),
+            })?)),
+            Tag::CodeBlock(kind) => {
+                let info = match &kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                Event::Html(CowStr::from(hook.render(context! {
+                    lang => info.split_whitespace().next(),
+                    info => info,
+                    code => plain_text(&inner),
+                    fenced => matches!(kind, CodeBlockKind::Fenced(_)),
+                })?))
+            }
+            Tag::BlockQuote(Some(kind)) => Event::Html(CowStr::from(hook.render(context! {
+                kind => format!("{:?}", kind).to_lowercase(),
+                text => Value::from_safe_string(to_html(self.apply(inner)?)?),
+            })?)),
+            _ => unreachable!("hook_for only matches hooked tags"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_hooks() {
+        let mut env = Environment::new();
+        env.add_template(IMAGE_HOOK, r#"<img loading="lazy" src="{{ src }}" alt="{{ alt }}">"#).unwrap();
+        env.add_template(CODEBLOCK_HOOK, r#"<pre data-lang="{{ lang }}">{{ code }}</pre>"#).unwrap();
+        let hooks = MdHooks::load(&env).unwrap();
+
+        let events = pulldown_cmark::Parser::new("![a *cat*](cat.webp) and [link](/x)\n\n```rust\nfn main() {}\n```\n").collect();
+        let html = to_html(hooks.apply(events).unwrap()).unwrap();
+        assert_eq!(html, "<p><img loading=\"lazy\" src=\"cat.webp\" alt=\"a cat\"> and <a href=\"/x\">link</a></p>\n<pre data-lang=\"rust\">fn main() {}\n</pre>");
+    }
+
+    #[test]
+    fn test_codeblock_hook_is_escaped() {
+        let mut env = Environment::new();
+        env.add_template(CODEBLOCK_HOOK, "<pre>{{ code }}</pre>").unwrap();
+        let hooks = MdHooks::load(&env).unwrap();
+
+        let events = pulldown_cmark::Parser::new("```\n<b>&</b>\n```\n").collect();
+        let html = to_html(hooks.apply(events).unwrap()).unwrap();
+        assert!(html.starts_with("<pre>&lt;b&gt;&amp;&lt;") && !html.contains("<b>"), "{}", html);
+    }
+
+    #[test]
+    fn test_callout_hook() {
+        let mut env = Environment::new();
+        env.add_template(CALLOUT_HOOK, r#"<aside class="{{ kind }}">{{ text }}</aside>"#).unwrap();
+        let hooks = MdHooks::load(&env).unwrap();
+
+        let events = pulldown_cmark::Parser::new_ext("> [!WARNING]\n> Hot\n\n> plain\n", pulldown_cmark::Options::ENABLE_GFM).collect();
+        let html = to_html(hooks.apply(events).unwrap()).unwrap();
+        assert_eq!(html, "<aside class=\"warning\"><p>Hot</p>\n</aside>\n<blockquote>\n<p>plain</p>\n</blockquote>\n");
+    }
+}
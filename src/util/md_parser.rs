@@ -1,14 +1,18 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::ops::{Deref, Range};
+use std::path::Path;
 use std::sync::Arc;
-use minijinja::{render, Environment, Error, State, Value};
+use minijinja::{context, escape_formatter, render, Environment, Error, Output, State, Value};
 use minijinja::value::{Enumerator, Object, ObjectExt, ObjectRepr};
 use pulldown_cmark::{CowStr, Event, HeadingLevel, MetadataBlockKind, Tag, TagEnd};
 use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
 use toml::Table;
-use crate::util::error_mappers::map_toml_error;
+use crate::config::MarkdownConfig;
+use crate::util::diagnostic::Diagnostic;
+use crate::util::md_hooks::MdHooks;
+use crate::util::md_refs::{rewrite_refs, RefResolver};
 
 #[derive(Debug, Clone)]
 pub struct MdValue {
@@ -17,6 +21,8 @@ pub struct MdValue {
 #[derive(Debug, Clone)]
 pub struct MdValueMap {
     lit: Option<String>,
+    /// `lit` is rendered html and is written without auto-escaping.
+    html: bool,
     attrs: HashMap<String, MdValue>,
 }
 
@@ -86,12 +92,7 @@ impl Object for MdValueMap {
         Self: Sized + 'static,
     {
         match &self.lit {
-            Some(lit) => {
-                if lit.contains("<p>") {
-                    return write!(f, "{}", lit);
-                }
-                write!(f, "{}", lit)
-            },
+            Some(lit) => write!(f, "{}", lit),
             None => {
                 let mut dbg = f.debug_map();
                 for (key, value) in self.attrs.iter() {
@@ -102,6 +103,19 @@ impl Object for MdValueMap {
         }
     }
 }
+/// Safe strings and html sections, the values that are written without escaping.
+pub fn is_html(value: &Value) -> bool {
+    value.is_safe() || matches!(value.downcast_object_ref::<MdValueMap>(), Some(MdValueMap { lit: Some(_), html: true, .. }))
+}
+
+/// Auto-escaping formatter that writes html sections as they are.
+pub fn md_formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), Error> {
+    if let Some(MdValueMap { lit: Some(lit), html: true, .. }) = value.downcast_object_ref::<MdValueMap>() {
+        return out.write_str(lit).map_err(Error::from);
+    }
+    escape_formatter(out, state, value)
+}
+
 struct MdValueIndex {
     name: String,
     idx: usize,
@@ -182,15 +196,17 @@ impl MdValueCursor {
                 0
             }
             Some(l) => {
-                l.list.push(MdValueMap { lit: None, attrs: HashMap::new() });
+                l.list.push(MdValueMap { lit: None, html: false, attrs: HashMap::new() });
                 l.list.len() - 1
             }
         };
         self.path.idx = new_idx;
         Ok(())
     }
-    fn set(&mut self, value: String) -> Result<(), Error> {
-        self.val.get_map_mut(self.path.as_ref())?.lit = Some(value);
+    fn set(&mut self, value: String, html: bool) -> Result<(), Error> {
+        let map = self.val.get_map_mut(self.path.as_ref())?;
+        map.lit = Some(value);
+        map.html = html;
         Ok(())
     }
 }
@@ -203,7 +219,7 @@ impl MdValue {
         Self{list: vec![]}
     }
     pub fn list(vec: Vec<String>) -> Self {
-        Self{list: vec.into_iter().map(|v| MdValueMap{lit: Some(v), attrs: HashMap::new()}).collect()}
+        Self{list: vec.into_iter().map(|v| MdValueMap{lit: Some(v), html: false, attrs: HashMap::new()}).collect()}
     }
     pub fn new_with_attrs(lit: String, attrs: HashMap<String, MdValue>) -> Self {
         Self::map(Some(lit), attrs)
@@ -212,7 +228,7 @@ impl MdValue {
         Self::map(None, attrs)
     }
     pub fn map(lit: Option<String>, attrs: HashMap<String, MdValue>) -> Self {
-        Self{list: vec![MdValueMap{lit, attrs}]}
+        Self{list: vec![MdValueMap{lit, html: false, attrs}]}
     }
 
     fn get_map_mut(&mut self, path: MdValueDeepIndexRef) -> Result<&mut MdValueMap, Error> {
@@ -233,22 +249,6 @@ impl MdValue {
             }
         }
     }
-
-    fn finalize(self, env: &Environment) -> Result<Self, Error> {
-        let mut out = Self::empty();
-        for m in self.list {
-            let lit_res = match m.lit {
-                None => None,
-                Some(l) => Some(env.render_str(l.as_str(), ())?)
-            };
-            let mut attrs_res = HashMap::new();
-            for (k, v) in m.attrs {
-                attrs_res.insert(k, v.finalize(env)?);
-            }
-            out.list.push(MdValueMap{lit: lit_res, attrs: attrs_res});
-        }
-        Ok(out)
-    }
 }
 
 
@@ -282,22 +282,22 @@ impl<'a> ParsingMode<'a> {
     pub fn text(events: Vec<Event<'a>>, range: Option<Range<usize>>) -> Self {
         Self::Text(ParsingModes::Text{events, range})
     }
-    pub fn frontmatter(events: Vec<Event<'a>>, style: MetadataBlockKind) -> Self {
-        Self::Frontmatter(ParsingModes::Frontmatter{events, style})
+    pub fn frontmatter(events: Vec<Event<'a>>, style: MetadataBlockKind, range: Range<usize>) -> Self {
+        Self::Frontmatter(ParsingModes::Frontmatter{events, style, range, text_range: None})
     }
-    pub fn ensure_frontmatter(&self) -> Result<&ParsingModes::Frontmatter, Error> {
+    pub fn ensure_frontmatter(&self) -> Result<&ParsingModes::Frontmatter<'a>, String> {
         match self {
             ParsingMode::Frontmatter(f) => Ok(f),
-            _ => Err(Error::custom("Parsing state is not set to Frontmatter")),
+            _ => Err("Parsing state is not set to Frontmatter".to_string()),
         }
     }
-    pub fn heading(section_type: SectionType, events: Vec<Event<'a>>, level: HeadingLevel) -> Self {
-        Self::Heading(ParsingModes::Heading{section_type, events, level})
+    pub fn heading(section_type: SectionType, templating: Option<bool>, events: Vec<Event<'a>>, level: HeadingLevel, range: Range<usize>) -> Self {
+        Self::Heading(ParsingModes::Heading{section_type, templating, events, level, range})
     }
-    pub fn ensure_heading(&self) -> Result<&ParsingModes::Heading, Error> {
+    pub fn ensure_heading(&self) -> Result<&ParsingModes::Heading<'a>, String> {
         match self {
             ParsingMode::Heading(h) => Ok(h),
-            _ => Err(Error::custom("Parsing state is not set to Heading")),
+            _ => Err("Parsing state is not set to Heading".to_string()),
         }
     }
 }
@@ -312,11 +312,13 @@ mod ParsingModes {
     pub struct Frontmatter<'a> {
         pub style: MetadataBlockKind,
         pub events: Vec<Event<'a>>,
+        pub range: Range<usize>,
+        pub text_range: Option<Range<usize>>,
     }
     impl<'a> Frontmatter<'a> {
-        pub fn ensure_style(&self, other: MetadataBlockKind) -> Result<(), Error> {
+        pub fn ensure_style(&self, other: MetadataBlockKind) -> Result<(), String> {
             if self.style != other {
-                Err(Error::custom("Frontmatter style mismatch"))
+                Err("Frontmatter style mismatch".to_string())
             } else {
                 Ok(())
             }
@@ -325,13 +327,15 @@ mod ParsingModes {
     #[derive(Debug)]
     pub struct Heading<'a> {
         pub section_type: SectionType,
+        pub templating: Option<bool>,
         pub level: HeadingLevel,
         pub events: Vec<Event<'a>>,
+        pub range: Range<usize>,
     }
     impl<'a> Heading<'a> {
-        pub fn ensure_level(&self, other: HeadingLevel) -> Result<(), Error> {
+        pub fn ensure_level(&self, other: HeadingLevel) -> Result<(), String> {
             if self.level != other {
-                Err(Error::custom("Heading level mismatch"))
+                Err("Heading level mismatch".to_string())
             } else {
                 Ok(())
             }
@@ -342,6 +346,7 @@ mod ParsingModes {
 struct HeadingData {
     name: String,
     section_type: SectionType,
+    templating: bool,
 }
 
 struct ParsingContext {
@@ -361,8 +366,16 @@ impl Default for ParsingContext {
 
 struct ContextBuilder<'a> {
     source: &'a String,
+    path: &'a Path,
+    current: Range<usize>,
+    hooks: MdHooks<'a>,
+    refs: Option<&'a dyn RefResolver>,
+    env: &'a Environment<'a>,
+    globals: Value,
+    options: pulldown_cmark::Options,
     context: ParsingContext,
     template: Option<String>,
+    templating: bool,
     parsing_mode: ParsingMode<'a>,
     // user_config: Option<Table>,
     // data: HashMap<String, MdValue>,
@@ -370,6 +383,17 @@ struct ContextBuilder<'a> {
     // stack: Vec<Heading<'a>>,
 }
 
+fn trim_trailing_newline(mut s: String) -> String {
+    if s.ends_with('\n') {
+        s.pop();
+    }
+    s
+}
+
+fn heading_marker(depth: usize) -> String {
+    "#".repeat(depth + 1)
+}
+
 fn heading_to_depth(h: HeadingLevel) -> usize {
     match h {
         HeadingLevel::H1 => 0,
@@ -382,17 +406,37 @@ fn heading_to_depth(h: HeadingLevel) -> usize {
 }
 
 impl<'a> ContextBuilder<'a> {
-    fn new(source: &'a String, default_template: &Option<String>) -> Self {
-        Self {
+    fn new(source: &'a String, p: ContextParams<'a>, options: pulldown_cmark::Options) -> Result<Self, Error> {
+        let mut defaults = p.defaults.clone();
+        defaults.remove("markdown");
+        let template = match defaults.remove("template") {
+            Some(toml::Value::String(template)) => Some(template),
+            _ => p.default_template.clone(),
+        };
+        let templating = matches!(defaults.remove("templating"), Some(toml::Value::Boolean(true)));
+        let context = ParsingContext { frontmatter: Some(defaults), ..Default::default() };
+        Ok(Self {
             source,
-            template: default_template.clone(),
-            context: ParsingContext::default(),
+            path: p.path,
+            current: 0..0,
+            hooks: MdHooks::load(p.env)?,
+            refs: p.refs,
+            env: p.env,
+            globals: p.globals,
+            options,
+            template,
+            templating,
+            context,
             parsing_mode: ParsingMode::None,
-        }
+        })
+    }
+
+    fn diagnostic<S: Into<String>>(&self, range: Range<usize>, message: S) -> Diagnostic {
+        Diagnostic::new(self.path, message).with_span(self.source, range)
     }
 
     fn finalize_frontmatter(&mut self, fm: ParsingModes::Frontmatter) -> Result<(), Error> {
-        let ParsingModes::Frontmatter {events, style} = fm;
+        let ParsingModes::Frontmatter {events, style, range, text_range} = fm;
 
         let mut frontmatter = String::new();
         for event in events {
@@ -400,19 +444,37 @@ impl<'a> ContextBuilder<'a> {
                 Event::Text(text) => {
                     frontmatter.push_str(&text);
                 }
-                _ => {return Err(Error::custom(format!("Invalid event in frontmatter: {:?}", event)))}
+                _ => {return Err(self.diagnostic(range, format!("Invalid event in frontmatter: {:?}", event)).into())}
             }
         }
         match style {
             MetadataBlockKind::YamlStyle => {
-                return Err(Error::custom("Yaml style frontmatter is not currently supported"));
+                return Err(self.diagnostic(range, "Yaml style frontmatter is not currently supported")
+                    .with_hint("use `+++` delimited TOML frontmatter instead of `---`")
+                    .into());
             }
             MetadataBlockKind::PlusesStyle => {
-                let mut table: Table = toml::from_str(&frontmatter).map_err(map_toml_error)?;
+                let mut table: Table = toml::from_str(&frontmatter).map_err(|e| {
+                    let offset = text_range.as_ref().map_or(range.start, |r| r.start);
+                    let span = e.span().map_or(range.clone(), |s| offset + s.start..offset + s.end);
+                    self.diagnostic(span, format!("Invalid TOML in frontmatter: {}", e.message()))
+                })?;
                 if let Some(toml::Value::String(template)) = table.remove("template") {
                     self.template = Some(template);
                 }
-                self.context.frontmatter = Some(table);
+                table.remove("markdown");
+                match table.remove("templating") {
+                    None => {}
+                    Some(toml::Value::Boolean(templating)) => self.templating = templating,
+                    Some(other) => {
+                        return Err(self.diagnostic(range, format!("Invalid `templating` value in frontmatter: {}", other))
+                            .with_hint("use `templating = true` or `templating = false`")
+                            .into())
+                    }
+                }
+                let mut config = self.context.frontmatter.take().unwrap_or_default();
+                merge_tables(&mut config, table);
+                self.context.frontmatter = Some(config);
             }
         }
         Ok(())
@@ -422,36 +484,85 @@ impl<'a> ContextBuilder<'a> {
         let ParsingModes::Text{events, range} = text;
         let Some(range) = range else {
             if !events.is_empty() {
-                return Err(Error::custom("Internal Error: Empty range in text section"));
+                return Err(self.diagnostic(self.current.clone(), "Internal Error: Empty range in text section").into());
             }
             return Ok(());
         };
         if self.context.heading_stack.is_empty() {
-            return Err(Error::custom("No section set for entry"))
+            return Err(self.diagnostic(range, "No section set for entry")
+                .with_hint("text must follow a heading, add a `# name` line above it")
+                .into())
         }
 
-        let data = match self.context.heading_stack.last().unwrap().section_type {
-            SectionType::Literal => {
-                self.source[range].to_string()
+        let HeadingData { section_type, templating, .. } = self.context.heading_stack.last().unwrap();
+        let html = matches!(section_type, SectionType::HTML);
+        // Template rendering drops a single trailing newline, other sections are trimmed the same way.
+        let data = match (section_type, templating) {
+            (SectionType::Literal, false) => {
+                trim_trailing_newline(self.source[range].to_string())
+            }
+            (SectionType::Literal, true) => {
+                self.render_template(range)?
             }
-            SectionType::HTML => {
-                let mut html = String::new();
-                pulldown_cmark::html::write_html_fmt(&mut html, events.into_iter())?;
-                html
+            (SectionType::HTML, false) => {
+                trim_trailing_newline(self.render_html(events, range)?)
+            }
+            (SectionType::HTML, true) => {
+                let text = self.render_template(range.clone())?;
+                trim_trailing_newline(self.render_html(pulldown_cmark::Parser::new_ext(&text, self.options).collect(), range)?)
             }
         };
-        self.context.cursor.set(data)?;
+        self.context.cursor.set(data, html).map_err(|e| self.diagnostic(self.current.clone(), e.to_string()))?;
         Ok(())
     }
 
+    fn render_template(&self, range: Range<usize>) -> Result<String, Error> {
+        let text = &self.source[range.clone()];
+        let ctx = context! {
+            config => Value::from_serialize(self.context.frontmatter.as_ref().unwrap_or(&Table::new())),
+            ..self.globals.clone()
+        };
+        self.env.render_named_str(&self.path.to_string_lossy(), text, ctx).map_err(|e| {
+            let line_start = e.line().map_or(0, |line| {
+                text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum()
+            });
+            let line_end = text[line_start..].find('\n').map_or(text.len(), |i| line_start + i);
+            self.diagnostic(range.start + line_start..range.start + line_end, format!("Failed to render templated section: {}", e))
+                .with_hint("templating is enabled for this section, wrap literal `{{` in `{% raw %}...{% endraw %}`")
+                .into()
+        })
+    }
+
+    fn render_html<'e>(&self, events: Vec<Event<'e>>, range: Range<usize>) -> Result<String, Error> {
+        let events = match self.refs {
+            Some(refs) => rewrite_refs(events, self.path, refs),
+            None => events,
+        };
+        let events = self.hooks.apply(events)
+            .map_err(|e| self.diagnostic(range.clone(), format!("Failed to render markdown hook: {}", e)))?;
+        let mut html = String::new();
+        pulldown_cmark::html::write_html_fmt(&mut html, events.into_iter())
+            .map_err(|e| self.diagnostic(range, format!("Failed to render section html: {}", e)))?;
+        Ok(html)
+    }
+
     fn finalize_heading(&mut self, h: ParsingModes::Heading) -> Result<(), Error> {
-        let ParsingModes::Heading{section_type, events, level} = h;
+        let ParsingModes::Heading{section_type, templating, events, level, range} = h;
         let target_depth = heading_to_depth(level);
         if self.context.heading_stack.len() < target_depth {
-            return Err(Error::custom("Heading stack underflow"));
+            let depth = self.context.heading_stack.len();
+            let hint = if depth == 0 {
+                format!("`{}` used before any `#`", heading_marker(target_depth))
+            } else {
+                format!("`{}` used directly under a `{}` heading, use at most `{}`", heading_marker(target_depth), heading_marker(depth - 1), heading_marker(depth))
+            };
+            return Err(self.diagnostic(range, "Heading stack underflow").with_hint(hint).into());
         }
         self.context.heading_stack.truncate(target_depth);
         self.context.cursor.truncate_path(target_depth);
+        let templating = templating.unwrap_or(
+            self.context.heading_stack.last().map_or(self.templating, |parent| parent.templating)
+        );
 
         let mut name = String::new();
         for event in events {
@@ -459,11 +570,15 @@ impl<'a> ContextBuilder<'a> {
                 Event::Text(text) => {
                     name.push_str(&text);
                 }
-                _ => {return Err(Error::custom(format!("Invalid event in section name: {:?}", event)))}
+                _ => {
+                    return Err(self.diagnostic(range, format!("Invalid event in section name: {:?}", event))
+                        .with_hint("section names must be plain text, without formatting, links or code")
+                        .into())
+                }
             }
         }
-        self.context.cursor.make_child(name.clone())?;
-        self.context.heading_stack.push(HeadingData{name, section_type});
+        self.context.cursor.make_child(name.clone()).map_err(|e| self.diagnostic(range, e.to_string()))?;
+        self.context.heading_stack.push(HeadingData{name, section_type, templating});
         Ok(())
     }
 
@@ -479,11 +594,15 @@ impl<'a> ContextBuilder<'a> {
         Ok(())
     }
 
-    fn finalize(mut self, env: &Environment) -> Result<Context, Error> {
+    fn finalize(mut self) -> Result<Context, Error> {
         self.finalize_section()?;
-        let data = self.context.cursor.finish().finalize(env)?.list.pop().unwrap();
+        let path = self.path;
+        let data = self.context.cursor.finish().list.pop().unwrap();
         Ok(Context {
-            template: self.template.ok_or(Error::custom("No template specified."))?,
+            template: self.template.ok_or(
+                Diagnostic::new(path, "No template specified.")
+                    .with_hint("set `template` in the `+++` frontmatter or `_defaults.toml`, or pass a default template to `blocks()`")
+            )?,
             config: self.context.frontmatter.unwrap_or(Default::default()),
             data: Value::from_object(data.clone()),
             ser_data: Some(data),
@@ -496,13 +615,16 @@ impl<'a> ContextBuilder<'a> {
         self.parsing_mode = ParsingMode::frontmatter(
             Vec::new(),
             style,
+            self.current.clone(),
         );
         Ok(())
     }
 
     fn handle_end_metadata(&mut self, end_style: MetadataBlockKind) -> Result<(), Error> {
-        let fm = self.parsing_mode.ensure_frontmatter()?;
-        fm.ensure_style(end_style)?;
+        let fm = self.parsing_mode.ensure_frontmatter()
+            .map_err(|e| self.diagnostic(self.current.clone(), e))?;
+        fm.ensure_style(end_style)
+            .map_err(|e| self.diagnostic(fm.range.clone(), e).with_hint("frontmatter opened with `+++` must be closed with `+++`"))?;
 
         self.finalize_section()?;
 
@@ -514,22 +636,38 @@ impl<'a> ContextBuilder<'a> {
         self.finalize_section()?;
 
         let mut st = SectionType::Literal;
+        let mut templating = None;
         for (attr, val) in attrs {
             if "html".eq(attr.as_ref()) && val.is_none() {
                 st = SectionType::HTML;
             }
+            if "templating".eq(attr.as_ref()) {
+                templating = match val.as_deref() {
+                    None | Some("true") => Some(true),
+                    Some("false") => Some(false),
+                    Some(other) => {
+                        return Err(self.diagnostic(self.current.clone(), format!("Invalid `templating` heading attribute: {}", other))
+                            .with_hint("use `{templating}` or `{templating=false}`")
+                            .into())
+                    }
+                };
+            }
         }
         self.parsing_mode = ParsingMode::heading(
             st,
+            templating,
             Vec::new(),
             level,
+            self.current.clone(),
         );
         Ok(())
     }
 
     fn handle_end_heading(&mut self, end_level: HeadingLevel) -> Result<(), Error> {
-        let head = self.parsing_mode.ensure_heading()?;
-        head.ensure_level(end_level)?;
+        let head = self.parsing_mode.ensure_heading()
+            .map_err(|e| self.diagnostic(self.current.clone(), e))?;
+        head.ensure_level(end_level)
+            .map_err(|e| self.diagnostic(head.range.clone(), e))?;
 
         self.finalize_section()?;
 
@@ -550,17 +688,29 @@ impl<'a> ContextBuilder<'a> {
                     }
                 }
             }
-            ParsingMode::Frontmatter(ParsingModes::Frontmatter { events, ..}) | ParsingMode::Heading(ParsingModes::Heading { events, ..}) => {
+            ParsingMode::Frontmatter(ParsingModes::Frontmatter { events, text_range, ..}) => {
+                events.push(node);
+                match text_range {
+                    None => {
+                        *text_range = Some(event_range);
+                    }
+                    Some(r) => {
+                        r.end = event_range.end;
+                    }
+                }
+            }
+            ParsingMode::Heading(ParsingModes::Heading { events, ..}) => {
                 events.push(node);
             }
             ParsingMode::None => {
-                return Err(Error::custom("Internal Error: Encountered data node with ParsingMode::None."))
+                return Err(self.diagnostic(event_range, "Internal Error: Encountered data node with ParsingMode::None.").into())
             }
         }
         Ok(())
     }
 
     fn handle(&mut self, node: Event<'a>, event_range: Range<usize>) -> Result<(), Error> {
+        self.current = event_range.clone();
         match node {
             Event::Start(Tag::MetadataBlock(style)) => self.handle_start_metadata(style)?,
             Event::End(TagEnd::MetadataBlock(end_style)) => self.handle_end_metadata(end_style)?,
@@ -584,22 +734,108 @@ pub struct Context {
 }
 
 
+pub struct ContextParams<'a> {
+    pub path: &'a Path,
+    pub default_template: &'a Option<String>,
+    pub env: &'a Environment<'a>,
+    pub refs: Option<&'a dyn RefResolver>,
+    pub markdown: &'a MarkdownConfig,
+    /// Extra variables for templated sections, merged next to `config`.
+    pub globals: Value,
+    /// Frontmatter of the `_defaults.toml` files above the block, its own frontmatter is merged on top.
+    pub defaults: &'a Table,
+}
+
+/// Merges `overrides` into `base`, nested tables are merged key by key and any other value is replaced.
+pub fn merge_tables(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => merge_tables(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+pub fn markdown_options(config: &MarkdownConfig) -> pulldown_cmark::Options {
+    use pulldown_cmark::Options;
+    let mut opt = Options::empty();
+    opt.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    opt.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+    opt.set(Options::ENABLE_TABLES, config.tables);
+    opt.set(Options::ENABLE_SMART_PUNCTUATION, config.smart_punctuation);
+    opt.set(Options::ENABLE_FOOTNOTES, config.footnotes);
+    opt.set(Options::ENABLE_STRIKETHROUGH, config.strikethrough);
+    opt.set(Options::ENABLE_TASKLISTS, config.tasklists);
+    opt.set(Options::ENABLE_GFM, config.alerts);
+    opt.set(Options::ENABLE_DEFINITION_LIST, config.definition_lists);
+    opt.set(Options::ENABLE_MATH, config.math);
+    opt
+}
+
+/// Applies the `[markdown]` table of the block frontmatter on top of the site config.
+/// Frontmatter has to be known before the block is parsed, so it is read in a separate pass.
+fn block_markdown_config(text: &str, path: &Path, config: &MarkdownConfig) -> Result<MarkdownConfig, Error> {
+    let mut parser = pulldown_cmark::Parser::new_ext(text, pulldown_cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS).into_offset_iter();
+    let Some((Event::Start(Tag::MetadataBlock(MetadataBlockKind::PlusesStyle)), range)) = parser.next() else {
+        return Ok(config.clone());
+    };
+    let mut frontmatter = String::new();
+    for (event, _) in parser {
+        match event {
+            Event::Text(text) => frontmatter.push_str(&text),
+            _ => break,
+        }
+    }
+    // Invalid frontmatter is reported with a proper span by the main pass.
+    let Ok(mut table) = toml::from_str::<Table>(&frontmatter) else {
+        return Ok(config.clone());
+    };
+    let overrides = match table.remove("markdown") {
+        None => return Ok(config.clone()),
+        Some(toml::Value::Table(overrides)) => overrides,
+        Some(_) => {
+            return Err(Diagnostic::new(path, "`markdown` in frontmatter must be a table")
+                .with_span(text, range)
+                .with_hint("use `[markdown]` followed by `footnotes = true` style keys")
+                .into())
+        }
+    };
+    let mut merged = toml::Table::try_from(config).map_err(|e| Error::custom(e.to_string()))?;
+    merged.extend(overrides);
+    merged.try_into().map_err(|e: toml::de::Error| {
+        Diagnostic::new(path, format!("Invalid `[markdown]` table in frontmatter: {}", e.message()))
+            .with_span(text, range)
+            .into()
+    })
+}
+
+/// Applies the `[markdown]` table of the directory defaults on top of the site config.
+fn defaults_markdown_config(defaults: &Table, path: &Path, config: &MarkdownConfig) -> Result<MarkdownConfig, Error> {
+    let overrides = match defaults.get("markdown") {
+        None => return Ok(config.clone()),
+        Some(toml::Value::Table(overrides)) => overrides.clone(),
+        Some(_) => return Err(Diagnostic::new(path, "`markdown` in `_defaults.toml` must be a table").into()),
+    };
+    let mut merged = toml::Table::try_from(config).map_err(|e| Error::custom(e.to_string()))?;
+    merge_tables(&mut merged, overrides);
+    merged.try_into().map_err(|e: toml::de::Error| {
+        Diagnostic::new(path, format!("Invalid `[markdown]` table in `_defaults.toml` for this block: {}", e.message())).into()
+    })
+}
+
 impl Context {
-    pub fn new(text: &String, default_template: &Option<String>, env: &Environment) -> Result<Self, Error> {
-        let parser = pulldown_cmark::Parser::new_ext(&text, {
-            let mut opt = pulldown_cmark::Options::empty();
-            opt.insert(pulldown_cmark::Options::ENABLE_TABLES);
-            opt.insert(pulldown_cmark::Options::ENABLE_SMART_PUNCTUATION);
-            opt.insert(pulldown_cmark::Options::ENABLE_HEADING_ATTRIBUTES);
-            opt.insert(pulldown_cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
-            opt
-        });
+    pub fn new(text: &String, p: ContextParams) -> Result<Self, Error> {
+        let markdown = defaults_markdown_config(p.defaults, p.path, p.markdown)?;
+        let options = markdown_options(&block_markdown_config(text, p.path, &markdown)?);
+        let parser = pulldown_cmark::Parser::new_ext(&text, options);
 
-        let mut context_builder = ContextBuilder::new(&text, &default_template);
+        let mut context_builder = ContextBuilder::new(&text, p, options)?;
         for (event, range) in parser.into_offset_iter() {
             context_builder.handle(event, range)?;
         }
-        Ok(context_builder.finalize(env)?)
+        Ok(context_builder.finalize()?)
     }
 }
 
@@ -641,7 +877,15 @@ bar
 baz
 ## another
 bunny
-"#.to_string(), &Some("hello".to_string()), &env);
+"#.to_string(), ContextParams {
+            path: Path::new("hello.md"),
+            default_template: &Some("hello".to_string()),
+            env: &env,
+            refs: None,
+            markdown: &Default::default(),
+            globals: Value::UNDEFINED,
+            defaults: &Table::new(),
+        });
         // println!("{:#?}", res);
         let res = res.unwrap();
         let template = env.get_template(res.template.as_str()).unwrap();
@@ -657,4 +901,101 @@ data.struct.field: bar
 data.struct.field.nested: baz
 data.struct.another: bunny"#)
     }
+
+    #[test]
+    fn templating_is_opt_in() {
+        let mut env = Environment::new();
+        env.add_template("t", "{{ data.a }}|{{ data.b }}|{{ data.c }}").unwrap();
+        let parse = |text: &str| {
+            let res = Context::new(&text.to_string(), ContextParams {
+                path: Path::new("t.md"),
+                default_template: &Some("t".to_string()),
+                env: &env,
+                refs: None,
+                markdown: &Default::default(),
+                globals: context! { site => "site" },
+                defaults: &Table::new(),
+            }).unwrap();
+            env.get_template("t").unwrap().render(res).unwrap()
+        };
+        assert_eq!(parse("# a\n{{ 1 + 1 }}\n# b\nx\n# c\ny\n"), "{{ 1 + 1 }}|x|y");
+        assert_eq!(
+            parse("+++\ntemplating = true\nname = \"n\"\n+++\n# a\n{{ config.name }} {{ site }}\n# b {templating=false}\n{{ raw }}\n# c {html}\n{{ \"q\" }} {% raw %}{{ x }}{% endraw %}\n"),
+            "n site|{{ raw }}|<p>q {{ x }}</p>",
+        );
+    }
+
+    #[test]
+    fn html_sections_skip_auto_escaping() {
+        let mut env = Environment::new();
+        env.set_formatter(md_formatter);
+        env.add_template("t.html", "{{ data.a }}|{{ data.b }}|{{ config.c }}").unwrap();
+        let res = Context::new(&"+++\nc = \"<i>\"\n+++\n# a\na < b\n# b {html}\n*x*\n".to_string(), ContextParams {
+            path: Path::new("t.md"),
+            default_template: &Some("t.html".to_string()),
+            env: &env,
+            refs: None,
+            markdown: &Default::default(),
+            globals: Value::UNDEFINED,
+            defaults: &Table::new(),
+        }).unwrap();
+        assert_eq!(env.get_template("t.html").unwrap().render(res).unwrap(), "a &lt; b|<p><em>x</em></p>|&lt;i&gt;");
+    }
+
+    #[test]
+    fn frontmatter_overrides_markdown_options() {
+        let mut env = Environment::new();
+        env.add_template("t", "{{ data.a }}").unwrap();
+        let parse = |text: &str| {
+            let res = Context::new(&text.to_string(), ContextParams {
+                path: Path::new("t.md"),
+                default_template: &Some("t".to_string()),
+                env: &env,
+                refs: None,
+                markdown: &Default::default(),
+                globals: Value::UNDEFINED,
+                defaults: &Table::new(),
+            }).unwrap();
+            env.get_template("t").unwrap().render(res).unwrap()
+        };
+        assert_eq!(parse("# a {html}\n~~x~~ \"y\"\n"), "<p>~~x~~ “y”</p>");
+        assert_eq!(parse("+++\n[markdown]\nstrikethrough = true\nsmart_punctuation = false\n+++\n# a {html}\n~~x~~ \"y\"\n"), "<p><del>x</del> \"y\"</p>");
+    }
+
+    #[test]
+    fn defaults_are_merged_under_frontmatter() {
+        let mut env = Environment::new();
+        env.add_template("card", "{{ config.class }}|{{ config.style.color }}|{{ config.style.size }}|{{ data.a }}").unwrap();
+        let defaults: Table = toml::from_str("template = \"card\"\nclass = \"card\"\n[style]\ncolor = \"red\"\nsize = 1\n").unwrap();
+        let res = Context::new(&"+++\nclass = \"wide\"\n[style]\nsize = 2\n+++\n# a\nx\n".to_string(), ContextParams {
+            path: Path::new("t.md"),
+            default_template: &Some("other".to_string()),
+            env: &env,
+            refs: None,
+            markdown: &Default::default(),
+            globals: Value::UNDEFINED,
+            defaults: &defaults,
+        }).unwrap();
+        assert_eq!(res.template, "card");
+        assert_eq!(env.get_template("card").unwrap().render(res).unwrap(), "wide|red|2|x");
+    }
+
+    #[test]
+    fn heading_underflow_diagnostic() {
+        let env = Environment::new();
+        let err = Context::new(&"# title\ntext\n### nested\nfoo\n".to_string(), ContextParams {
+            path: Path::new("blocks/1. card.md"),
+            default_template: &Some("hello".to_string()),
+            env: &env,
+            refs: None,
+            markdown: &Default::default(),
+            globals: Value::UNDEFINED,
+            defaults: &Table::new(),
+        }).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("Heading stack underflow"), "{}", msg);
+        assert!(msg.contains("blocks/1. card.md:3:1"), "{}", msg);
+        assert!(msg.contains("3 | ### nested"), "{}", msg);
+        assert!(msg.contains("hint: `###` used directly under a `#` heading, use at most `##`"), "{}", msg);
+    }
 }
\ No newline at end of file
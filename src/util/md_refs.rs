@@ -0,0 +1,82 @@
+use std::path::{Component, Path, PathBuf};
+use pulldown_cmark::{CowStr, Event, Tag};
+use url::Url;
+
+/// Maps references found in markdown sections to their final urls.
+pub trait RefResolver {
+    /// Resolves an image `src` relative to the block file at `block`.
+    fn resolve_image(&self, block: &Path, src: &str) -> Option<String>;
+    /// Resolves a link to another markdown source relative to the block file at `block`.
+    fn resolve_link(&self, block: &Path, href: &str) -> Option<String>;
+}
+
+pub fn is_relative_ref(r: &str) -> bool {
+    !(r.is_empty() || r.starts_with('/') || r.starts_with('#') || Url::parse(r).is_ok())
+}
+
+/// Splits `page.md#anchor` into `("page.md", "#anchor")`.
+pub fn split_fragment(r: &str) -> (&str, &str) {
+    match r.find(['#', '?']) {
+        Some(idx) => r.split_at(idx),
+        None => (r, ""),
+    }
+}
+
+/// Lexically resolves `.` and `..` components without touching the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn is_md_link(href: &str) -> bool {
+    split_fragment(href).0.ends_with(".md")
+}
+
+pub fn rewrite_refs<'e>(events: Vec<Event<'e>>, block: &Path, resolver: &dyn RefResolver) -> Vec<Event<'e>> {
+    events.into_iter().map(|event| match event {
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) if is_relative_ref(&dest_url) => {
+            let dest_url = resolver.resolve_image(block, &dest_url).map(CowStr::from).unwrap_or(dest_url);
+            Event::Start(Tag::Image { link_type, dest_url, title, id })
+        }
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) if is_relative_ref(&dest_url) && is_md_link(&dest_url) => {
+            let dest_url = resolver.resolve_link(block, &dest_url).map(CowStr::from).unwrap_or(dest_url);
+            Event::Start(Tag::Link { link_type, dest_url, title, id })
+        }
+        event => event,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Prefixer;
+    impl RefResolver for Prefixer {
+        fn resolve_image(&self, _: &Path, src: &str) -> Option<String> {
+            Some(format!("/static/{}", src))
+        }
+        fn resolve_link(&self, _: &Path, href: &str) -> Option<String> {
+            let (_, fragment) = split_fragment(href);
+            Some(format!("/page/{}", fragment))
+        }
+    }
+
+    #[test]
+    fn test_rewrite_refs() {
+        let events = pulldown_cmark::Parser::new("![](a.webp) ![](https://x.org/b.webp) [p](other.md#top) [q](other.html) [r](/abs.md)").collect();
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, rewrite_refs(events, Path::new("block.md"), &Prefixer).into_iter());
+        assert_eq!(html, "<p><img src=\"/static/a.webp\" alt=\"\" /> <img src=\"https://x.org/b.webp\" alt=\"\" /> <a href=\"/page/#top\">p</a> <a href=\"other.html\">q</a> <a href=\"/abs.md\">r</a></p>\n");
+    }
+}
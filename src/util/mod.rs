@@ -1,3 +1,7 @@
 pub mod html;
 pub mod md_parser;
+pub mod md_hooks;
+pub mod md_refs;
 pub mod error_mappers;
+pub mod diagnostic;
+pub mod ordering;
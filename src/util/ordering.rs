@@ -0,0 +1,63 @@
+use std::cmp::Ordering;
+
+/// Splits the `1. ` / `01-` / `2_` ordering prefix off a file stem: `"10. about us"` is `(Some(10), "about us")`.
+pub fn split_order_prefix(stem: &str) -> (Option<u64>, &str) {
+    let digits = stem.len() - stem.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &stem[digits..];
+    let title = rest.trim_start_matches(['.', '-', '_', ' ']);
+    if digits == 0 || title.len() == rest.len() || title.is_empty() {
+        return (None, stem);
+    }
+    (stem[..digits].parse().ok(), title)
+}
+
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest.find(|c: char| c.is_ascii_digit() != first.is_ascii_digit()).unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Compares runs of digits by their value, so `2. b` comes before `10. a`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        let (a, b) = match (a_chunks.next(), b_chunks.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a, b),
+        };
+        let numeric = a.starts_with(|c: char| c.is_ascii_digit()) && b.starts_with(|c: char| c.is_ascii_digit());
+        let ordering = match numeric {
+            true => a.trim_start_matches('0').len().cmp(&b.trim_start_matches('0').len())
+                .then_with(|| a.trim_start_matches('0').cmp(b.trim_start_matches('0'))),
+            false => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordering() {
+        assert_eq!(split_order_prefix("10. about us"), (Some(10), "about us"));
+        assert_eq!(split_order_prefix("02-intro"), (Some(2), "intro"));
+        assert_eq!(split_order_prefix("2024"), (None, "2024"));
+        assert_eq!(split_order_prefix("3d models"), (None, "3d models"));
+
+        let mut names = vec!["10. foo", "2. bar", "b", "1. baz", "a2", "a10"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["1. baz", "2. bar", "10. foo", "a2", "a10", "b"]);
+    }
+}